@@ -0,0 +1,101 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            _ => Err(format!("Unknown waveform {} (expected square, sine or triangle)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BeeperConfig {
+    /// tone frequency in Hz
+    pub frequency: f32,
+    /// output volume, between 0.0 and 1.0
+    pub volume: f32,
+    pub waveform: Waveform,
+}
+
+impl Default for BeeperConfig {
+    fn default() -> Self {
+        BeeperConfig {
+            frequency: 440.0,
+            volume: 0.2,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+/// Tone generator for the buzzer driven by the sound timer
+pub struct Beeper {
+    config: BeeperConfig,
+    on: bool,
+    /// position in the current period, between 0.0 and 1.0
+    phase: f32,
+}
+
+impl Beeper {
+    pub fn new(config: BeeperConfig) -> Beeper {
+        Beeper {
+            config,
+            on: false,
+            phase: 0.0,
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.config.volume
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// Fill `out` with the next samples at `sample_rate` Hz, between -1.0 and 1.0, silence when off
+    pub fn fill(&mut self, out: &mut [f32], sample_rate: u32) {
+        if !self.on {
+            out.iter_mut().for_each(|sample| *sample = 0.0);
+            self.phase = 0.0;
+            return;
+        }
+
+        let step = self.config.frequency / sample_rate as f32;
+        for sample in out.iter_mut() {
+            *sample = match self.config.waveform {
+                Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Sine => (2.0 * PI * self.phase).sin(),
+                Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            };
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+#[test]
+fn beeper_waveforms() {
+    let mut out = vec![0.0; 4];
+    for (waveform, expected) in [(Waveform::Square, [1.0, 1.0, -1.0, -1.0]),
+                                 (Waveform::Triangle, [-1.0, 0.0, 1.0, 0.0])].iter() {
+        let mut beeper = Beeper::new(BeeperConfig { frequency: 1000.0, volume: 1.0, waveform: *waveform });
+        beeper.fill(&mut out, 4000);
+        assert_eq!(out, vec![0.0; 4]);
+
+        beeper.set_on(true);
+        beeper.fill(&mut out, 4000);
+        assert_eq!(&out[..], &expected[..]);
+    }
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use chip8::chip8::{assemble_file, compile_octo_file};
+
+const USAGE: &str = "Usage: chip8-asm <source> [-o <output>]
+Assembles a Cowgod-syntax source, or an Octo source ending in .8o, into a ROM loaded at 0x200, written next to the source with a .ch8 extension by default";
+
+fn main() {
+    let mut source_path = None;
+    let mut output = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" if output.is_none() => output = args.next(),
+            _ if source_path.is_none() && !arg.starts_with('-') => source_path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    let source_path = match source_path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = if source_path.ends_with(".8o") {
+        compile_octo_file(&source_path)
+    } else {
+        assemble_file(&source_path)
+    };
+    let rom = rom.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    let output = output.unwrap_or_else(|| Path::new(&source_path).with_extension("ch8").display().to_string());
+    fs::write(&output, rom).unwrap_or_else(|err| {
+        eprintln!("{}: {}", output, err);
+        process::exit(1);
+    });
+}
@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::process;
+
+use chip8::chip8::disassemble_rom;
+
+const USAGE: &str = "Usage: chip8-disasm <rom> [-o <output>]
+Disassembles a ROM loaded at 0x200 into a labelled listing, written to stdout by default";
+
+fn main() {
+    let mut rom_path = None;
+    let mut output = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" if output.is_none() => output = args.next(),
+            _ if rom_path.is_none() && !arg.starts_with('-') => rom_path = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = fs::read(&rom_path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", rom_path, err);
+        process::exit(1);
+    });
+    let listing = format!("; {}\n{}", rom_path, disassemble_rom(&rom));
+
+    match output {
+        Some(path) => fs::write(&path, listing).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }),
+        None => print!("{}", listing),
+    }
+}
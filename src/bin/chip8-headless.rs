@@ -0,0 +1,206 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+use chip8::chip8::{compile_octo_file, framebuffer_ascii, framebuffer_hash, framebuffer_pbm, framebuffer_png};
+use chip8::chip8::{Chip8, KeyScript, Movie, NullBackend, Quirks, QuirksPreset, RngKind, Timing};
+use chip8::chip8::{DEFAULT_HASH_INTERVAL, FRAME_FREQUENCY, INSTR_PER_FRAME};
+
+const USAGE: &str = "Usage: chip8-headless [options] <rom>
+Runs a ROM without a window or audio, then prints the hash of the final display
+Options:
+    --frames <n>                        number of 60Hz frames to run (default 60, or the movie length)
+    --keys <script>                     keys held from a frame on, e.g. \"30=5 35= 100=4A\"
+    --keys-file <file>                  read the key script from a file
+    --play <file>                       replay a movie file instead of the key script, fails on desync
+    --record <file>                     record the keys of every frame to a movie file
+    --hash-interval <frames>            frames between state hashes in recorded movies, 0 disables (default 60)
+    --quirks vip|chip48|schip|xochip    interpreter quirks preset
+    --ipf <n>                           instructions executed per 60Hz frame (default 8)
+    --hz <n>                            instructions executed per second, instead of --ipf
+    --timing fixed|vip                  instruction timing, vip charges COSMAC VIP machine cycles (default fixed)
+    --seed <n>                          seed of the random number generator (default 0)
+    --rng xorshift|table                random number generator, table adds bytes of the font area
+    --format ascii|pbm|png              format of the display dump (default ascii)
+    --scale <n>                         PNG pixel size (default 1)
+    -o, --output <file>                 write the display dump to file, stdout for ascii by default";
+
+enum Format {
+    Ascii,
+    Pbm,
+    Png,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Format::Ascii),
+            "pbm" => Ok(Format::Pbm),
+            "png" => Ok(Format::Png),
+            _ => Err(format!("Unknown format {} (expected ascii, pbm or png)", s)),
+        }
+    }
+}
+
+struct Options {
+    frames: Option<u32>,
+    keys: KeyScript,
+    play_path: Option<String>,
+    record_path: Option<String>,
+    hash_interval: u32,
+    quirks: Quirks,
+    cpu_frequency: u32,
+    timing: Timing,
+    seed: u64,
+    rng: RngKind,
+    format: Format,
+    scale: usize,
+    output: Option<String>,
+    rom_path: String,
+}
+
+/// Parse the value following option `name`
+fn parse_value<T: FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for {}", name))?;
+    value.parse::<T>().map_err(|_| format!("Invalid value {} for {}", value, name))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut frames = None;
+    let mut keys = KeyScript::default();
+    let mut play_path = None;
+    let mut record_path = None;
+    let mut hash_interval = DEFAULT_HASH_INTERVAL;
+    let mut quirks = Quirks::default();
+    let mut cpu_frequency = INSTR_PER_FRAME * FRAME_FREQUENCY;
+    let mut timing = Timing::Fixed;
+    let mut seed = 0;
+    let mut rng = RngKind::XorShift;
+    let mut format = Format::Ascii;
+    let mut scale = 1;
+    let mut output = None;
+    let mut rom_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = Some(parse_value(&arg, args.next())?),
+            "--keys" => keys = args.next().unwrap_or_default().parse()?,
+            "--keys-file" => {
+                let path = parse_value::<String>(&arg, args.next())?;
+                keys = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path, err))?.parse()?;
+            }
+            "--play" => play_path = Some(parse_value(&arg, args.next())?),
+            "--record" => record_path = Some(parse_value(&arg, args.next())?),
+            "--hash-interval" => hash_interval = parse_value(&arg, args.next())?,
+            "--quirks" => quirks = Quirks::from(args.next().unwrap_or_default().parse::<QuirksPreset>()?),
+            "--ipf" => cpu_frequency = parse_value::<u32>(&arg, args.next())?.checked_mul(FRAME_FREQUENCY)
+                .ok_or(format!("Value too large for {}", arg))?,
+            "--hz" => cpu_frequency = parse_value(&arg, args.next())?,
+            "--timing" => timing = args.next().unwrap_or_default().parse()?,
+            "--seed" => seed = parse_value(&arg, args.next())?,
+            "--rng" => rng = args.next().unwrap_or_default().parse()?,
+            "--format" => format = args.next().unwrap_or_default().parse()?,
+            "--scale" => scale = parse_value(&arg, args.next())?,
+            "-o" | "--output" => output = Some(parse_value(&arg, args.next())?),
+            _ if rom_path.is_none() && !arg.starts_with('-') => rom_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    Ok(Options {
+        frames,
+        keys,
+        play_path,
+        record_path,
+        hash_interval,
+        quirks,
+        cpu_frequency,
+        timing,
+        seed,
+        rng,
+        format,
+        scale,
+        output,
+        rom_path: rom_path.ok_or_else(|| USAGE.to_string())?,
+    })
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let rom = if options.rom_path.ends_with(".8o") {
+        compile_octo_file(&options.rom_path).map_err(|err| err.to_string())
+    } else {
+        fs::read(&options.rom_path).map_err(|err| format!("{}: {}", options.rom_path, err))
+    };
+    let rom = rom.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    let mut chip8 = Chip8::new_with_quirks(NullBackend, options.quirks);
+    chip8.set_rng(options.rng.create(Some(options.seed)));
+    chip8.set_cpu_frequency(options.cpu_frequency);
+    chip8.set_timing(options.timing);
+    if let Err(err) = chip8.load_rom(rom.clone()) {
+        eprintln!("{}: {}", options.rom_path, err);
+        process::exit(1);
+    }
+    let mut frames = options.frames.unwrap_or(60);
+    if let Some(path) = &options.play_path {
+        let movie = Movie::load(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+        frames = options.frames.unwrap_or_else(|| movie.len());
+        if let Err(err) = chip8.play_movie(movie, &rom) {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    } else if options.record_path.is_some() {
+        chip8.start_recording(&rom, options.seed, options.rng, options.hash_interval);
+    }
+    let result = chip8.run_headless(frames, &options.keys);
+    let desync = chip8.movie_desync();
+    if let (Some(movie), Some(path)) = (chip8.stop_movie(), &options.record_path) {
+        movie.save(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+    }
+
+    let pixels = chip8.pixels();
+    let dump = match options.format {
+        Format::Ascii => framebuffer_ascii(pixels).into_bytes(),
+        Format::Pbm => framebuffer_pbm(pixels),
+        Format::Png => framebuffer_png(pixels, chip8.palette(), options.scale),
+    };
+    match &options.output {
+        Some(path) => fs::write(path, dump).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }),
+        None if matches!(options.format, Format::Ascii) => print!("{}", String::from_utf8_lossy(&dump)),
+        None => {}
+    }
+    println!("{:08x}", framebuffer_hash(pixels));
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    if let Some(desync) = desync {
+        eprintln!("{}", desync);
+        process::exit(1);
+    }
+}
@@ -1,47 +1,128 @@
-use std::thread::sleep;
-use std::time::{Duration, Instant};
-
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+pub use asm::{assemble, assemble_file, AsmError};
+pub use audio::{AudioStream, AUDIO_PATTERN_SIZE};
+pub use backend::{Backend, Command, NullBackend, Palette, DEFAULT_PALETTE};
+pub use condition::Condition;
+pub use debugger::{Access, Breakpoint, BreakReason, Debugger, Register, WatchKind, Watchpoint};
+pub use disasm::{Instruction, Octo};
+pub use listing::{disassemble_rom, PROGRAM_START};
+pub use movie::{Desync, Movie, MovieError, MovieHeader, DEFAULT_HASH_INTERVAL};
+use movie::ActiveMovie;
+pub use octo::{compile_octo, compile_octo_file};
 use display::Display;
 use display::Sprite;
-use registers::Registers;
-
-use crate::chip8::display::FONT;
-
+pub use error::EmulatorError;
+pub use headless::{framebuffer_ascii, framebuffer_hash, framebuffer_pbm, framebuffer_png, KeyScript};
+pub use quirks::{MemoryIncrement, Quirks, QuirksPreset};
+pub use registers::Registers;
+pub use rng::{Rng, RngKind, TableRng, XorShiftRng};
+use rewind::RewindBuffer;
+use scheduler::FrameScheduler;
+pub use timing::{Timing, VIP_CPU_CYCLES_PER_FRAME, VIP_FRAME_CYCLES};
+use timing::vip_cycles;
+pub use state::{SAVE_SLOTS, StateError};
+use state::crc32;
+pub use trace::Tracer;
+
+use crate::chip8::display::{ALL_PLANES, BIG_FONT, FONT};
+
+mod asm;
+mod audio;
+mod backend;
+mod condition;
+mod debugger;
+mod disasm;
 mod registers;
+mod rewind;
+mod rng;
+mod scheduler;
 mod display;
+mod error;
+mod headless;
+mod listing;
+mod movie;
+mod octo;
+mod quirks;
+mod state;
+mod timing;
+mod trace;
 
 #[cfg(test)]
 mod chip8_tests;
 
-const MEM_SIZE: usize = 4 * 1024;
+/// XO-CHIP 64 KiB address space
+const MEM_SIZE: usize = 64 * 1024;
 const KBD_SIZE: usize = 16;
 
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 
-pub struct Chip8<'a> {
+/// SUPER-CHIP high resolution mode
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+
+/// SUPER-CHIP large font is stored right after the regular font
+const BIG_FONT_ADDR: usize = FONT.len();
+/// Number of SUPER-CHIP RPL user flags
+const RPL_FLAGS_SIZE: usize = 16;
+
+pub struct Chip8<B: Backend = NullBackend> {
     running: bool,
+    /// set by 00FD - EXIT, no more instructions are executed
+    exited: bool,
+    /// set once the backend asked to quit
+    quit_requested: bool,
+    /// buzzer state last reported to the backend
+    sound_on: bool,
     memory: Vec<u8>,
     regs: Registers,
     display: Display,
     keypad: Vec<bool>,
-
-    /// legacy mode:
-    /// SHR Vx, Vy => VF = Vy & 1; Vx = Vy >> 1;
-    /// SHL Vx, Vy => VF = Vy & 1; Vx = Vy << 1;
-    ///
-    /// Non-legacy-mode:
-    /// SHR Vx, Vy => VF = Vx & 1; Vx = Vx >> 1
-    /// SHL Vx, Vy => VF = Vx & 1; Vx = Vx << 1;
-    legacy_mode: bool,
-
-    render: Option<&'a mut dyn FnMut(&Vec<Vec<u8>>)>,
-    play_sound: Option<&'a dyn Fn()>,
-    check_input: Option<&'a mut dyn FnMut(&mut bool, &mut Vec<bool>)>,
+    /// SUPER-CHIP RPL user flags, saved and restored by Fx75/Fx85
+    rpl_flags: Vec<u8>,
+    /// colors of the 4 XO-CHIP plane combinations
+    palette: Palette,
+    audio: AudioStream,
+    /// save slots are stored next to this path, see set_save_path()
+    save_path: Option<PathBuf>,
+    /// per-frame states, see set_rewind_depth()
+    rewind: RewindBuffer,
+    /// true while the frontend is rewinding, emulation is paused
+    rewinding: bool,
+    debugger: Debugger,
+    /// execution log, see set_tracer()
+    tracer: Option<Tracer>,
+    /// source of Cxkk - RND, see set_rng()
+    rng: Box<dyn Rng>,
+    /// movie being recorded or played back, see start_recording() and play_movie()
+    movie: Option<ActiveMovie>,
+    /// instructions executed per second, see set_cpu_frequency()
+    cpu_frequency: u32,
+    /// fraction of an instruction carried over to the next frame, in 1/FRAME_FREQUENCY
+    cycle_remainder: u32,
+    /// instruction costs, see set_timing()
+    timing: Timing,
+    /// machine cycles used in the current frame with Timing::CosmacVip, may overrun into the next frame
+    frame_cycles: u32,
+    /// paces run_realtime_frame()
+    scheduler: FrameScheduler,
+
+    quirks: Quirks,
+    /// set by the 60Hz timer interrupt, cleared by Dxyn when quirks.display_wait is on
+    vblank: bool,
+
+    backend: B,
 }
 
 const INSTR_SIZE: u16 = 2;
 
+/// Timers, input and display refresh rate
+pub const FRAME_FREQUENCY: u32 = 60;
+/// Default number of instructions executed per 60Hz frame, see `set_cpu_frequency()`
+pub const INSTR_PER_FRAME: u32 = 8;
+
 #[derive(Debug)]
 enum Opcode {
     Imm { op: u8, nnn: u16 },
@@ -49,8 +130,10 @@ enum Opcode {
     RegReg { op: u8, x: usize, y: usize, op2: u8 },
 }
 
-impl From<u16> for Opcode {
-    fn from(opcode: u16) -> Self {
+impl TryFrom<u16> for Opcode {
+    type Error = u16;
+
+    fn try_from(opcode: u16) -> Result<Self, Self::Error> {
         let op = (opcode >> 12) as u8;
         let nnn = opcode & 0xFFF;
         let x = ((opcode >> 8) & 0xF) as usize;
@@ -59,121 +142,591 @@ impl From<u16> for Opcode {
         let op2 = (opcode & 0xF) as u8;
 
         match op {
-            0 | 1 | 2 | 0xA | 0xB => Opcode::Imm { op, nnn },
-            3 | 4 | 6 | 7 | 0xC | 0xE | 0xF => Opcode::RegImm { op, x, kk },
-            5 | 8 | 9 | 0xD => Opcode::RegReg { op, x, y, op2 },
-            _ => panic!("Unknown instruction opcode {:X} when decoding", op)
+            0 | 1 | 2 | 0xA | 0xB => Ok(Opcode::Imm { op, nnn }),
+            3 | 4 | 6 | 7 | 0xC | 0xE | 0xF => Ok(Opcode::RegImm { op, x, kk }),
+            5 | 8 | 9 | 0xD => Ok(Opcode::RegReg { op, x, y, op2 }),
+            _ => Err(opcode)
         }
     }
 }
 
-impl Chip8<'_> {
-    pub fn new<'a>() -> Chip8<'a> {
+/// Registers x through y, in descending order if x > y
+fn reg_range(x: usize, y: usize) -> Box<dyn Iterator<Item=usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+impl Chip8<NullBackend> {
+    pub fn new() -> Chip8<NullBackend> {
+        Chip8::new_with_backend(NullBackend)
+    }
+}
+
+impl Default for Chip8<NullBackend> {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
+impl<B: Backend> Chip8<B> {
+    pub fn new_with_backend(backend: B) -> Chip8<B> {
+        Chip8::new_with_quirks(backend, Quirks::default())
+    }
+
+    pub fn new_with_quirks(backend: B, quirks: Quirks) -> Chip8<B> {
         let mut chip8 = Chip8 {
             running: false,
+            exited: false,
+            quit_requested: false,
+            sound_on: false,
             memory: vec![0; MEM_SIZE],
             regs: Registers::new(),
             display: Display::new(),
             keypad: vec![true; KBD_SIZE],
-            legacy_mode: false,
-            render: None,
-            play_sound: None,
-            check_input: None,
+            rpl_flags: vec![0; RPL_FLAGS_SIZE],
+            palette: DEFAULT_PALETTE,
+            audio: AudioStream::new(),
+            save_path: None,
+            rewind: RewindBuffer::new(0),
+            rewinding: false,
+            debugger: Debugger::new(),
+            tracer: None,
+            rng: Box::new(XorShiftRng::from_entropy()),
+            movie: None,
+            cpu_frequency: INSTR_PER_FRAME * FRAME_FREQUENCY,
+            cycle_remainder: 0,
+            timing: Timing::Fixed,
+            frame_cycles: 0,
+            scheduler: FrameScheduler::new(FRAME_FREQUENCY),
+            quirks,
+            vblank: false,
+            backend,
         };
 
         // store font data
-        for i in 0..FONT.len() {
-            chip8.memory[i] = FONT[i];
-        }
+        chip8.memory[..FONT.len()].copy_from_slice(&FONT);
+        chip8.memory[BIG_FONT_ADDR..BIG_FONT_ADDR + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
 
         chip8
     }
 
-    pub fn new_with_backend<'a>(render: &'a mut dyn FnMut(&Vec<Vec<u8>>), play_sound: &'a dyn Fn(), check_input: &'a mut dyn FnMut(&mut bool, &mut Vec<bool>)) -> Chip8<'a> {
-        let mut chip8 = Chip8::new();
-        chip8.render = Some(render);
-        chip8.play_sound = Some(play_sound);
-        chip8.check_input = Some(check_input);
-        chip8
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Instructions executed per second
+    pub fn cpu_frequency(&self) -> u32 {
+        self.cpu_frequency
+    }
+
+    /// Execute `hz` instructions per second, spread over the 60Hz frames. When `hz` is not
+    /// a multiple of 60, frames alternate between `hz / 60` and `hz / 60 + 1` instructions.
+    /// The timers keep ticking once per frame whatever the speed.
+    pub fn set_cpu_frequency(&mut self, hz: u32) {
+        self.cpu_frequency = hz;
+        self.cycle_remainder = 0;
+    }
+
+    /// Execute `ipf` instructions per 60Hz frame, up to `u32::MAX` instructions per second
+    pub fn set_instructions_per_frame(&mut self, ipf: u32) {
+        self.set_cpu_frequency(ipf.saturating_mul(FRAME_FREQUENCY));
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// With `Timing::CosmacVip`, each frame executes instructions until the VIP_CPU_CYCLES_PER_FRAME
+    /// machine cycles left by the display are used, the CPU frequency being ignored.
+    /// The timers tick where the frame's cycles run out, an instruction overrunning
+    /// the frame delays the next one.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.frame_cycles = 0;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn audio(&self) -> &AudioStream {
+        &self.audio
+    }
+
+    pub fn audio_mut(&mut self) -> &mut AudioStream {
+        &mut self.audio
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.regs
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.regs
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Why `run()` or `run_frame()` stopped early, None while running normally
+    pub fn break_reason(&self) -> Option<BreakReason> {
+        self.debugger.break_reason()
+    }
+
+    /// Clear the current break, the next call to `run()` continues from PC
+    /// even if a breakpoint is set on it
+    pub fn resume(&mut self) {
+        self.debugger.resume(self.regs.pc);
+    }
+
+    /// Make Cxkk - RND reproducible, the same seed gives the same sequence of random bytes
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    /// Replace the source of Cxkk - RND, XorShiftRng seeded from the operating system by default
+    pub fn set_rng(&mut self, rng: Box<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    /// Log every instruction executed by `step()`, None stops tracing
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
     }
 
     pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), std::io::Error> {
-        for byte in rom {
-            self.memory[self.regs.pc as usize] = byte;
-            self.regs.pc += 1;
+        let start = self.regs.pc as usize;
+        if start + rom.len() > self.memory.len() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                           format!("ROM too large ({} bytes)", rom.len())));
         }
+        self.memory[start..start + rom.len()].copy_from_slice(&rom);
 
         self.regs.pc = 0x200;
 
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), std::io::Error> {
+    /// Run at 60 frames per second until the user quits, the program exits
+    /// or a breakpoint is hit, see `break_reason()`.
+    pub fn run(&mut self) -> Result<(), EmulatorError> {
         self.running = true;
+        self.scheduler.reset();
+        let mut result = Ok(());
         while self.running {
-            let old_frame_time = Instant::now();
-
-            while old_frame_time.elapsed() < Duration::from_millis(1000 / 60 /* 1/60Hz */) {
-                let pc = self.regs.pc as usize;
-                let instr = ((self.memory[pc] as u16) << 8) | (self.memory[pc + 1] as u16);
-                self.exec_instr(instr);
-                sleep(Duration::from_millis(1000 / 500 /* 1 / 500Hz */));
+            match self.run_realtime_frame() {
+                Ok(true) => {}
+                Ok(false) => self.running = false,
+                Err(err) => {
+                    result = Err(err);
+                    self.running = false;
+                }
             }
+        } // end while(running)
 
-            if self.regs.st > 0 {
-                self.regs.st -= 1;
-            }
+        if self.sound_on {
+            self.sound_on = false;
+            self.backend.set_sound(false);
+        }
+
+        result
+    }
 
-            if self.regs.dt > 0 {
-                if let Some(play_sound) = &self.play_sound {
-                    play_sound();
+    /// Execute one frame, exchange input, audio and commands with the backend, render the display
+    /// and sleep until the next frame is due. Frames are paced against the monotonic clock at exactly
+    /// 60 per second, sleep overshoot being made up by the following frames. Used by `run()` and by
+    /// frontends driving their own loop. Returns false once the user quit, the program exited or a break occurred.
+    pub fn run_realtime_frame(&mut self) -> Result<bool, EmulatorError> {
+        if !self.rewinding {
+            self.run_frame()?;
+        }
+
+        let sound_on = self.regs.st > 0;
+        if sound_on != self.sound_on {
+            self.sound_on = sound_on;
+            self.backend.set_sound(sound_on);
+        }
+        self.backend.queue_audio(&mut self.audio);
+
+        self.rewinding = false;
+        let alive = self.poll_backend();
+
+        self.backend.render(self.display.pixels(), &self.palette);
+
+        self.scheduler.wait();
+
+        Ok(alive && !self.exited && self.debugger.break_reason().is_none())
+    }
+
+    /// Keep the backend alive while emulation is paused, e.g. by a debugger:
+    /// silence the buzzer, process input and commands and render the display.
+    /// Returns false once the user asked to quit.
+    pub fn refresh(&mut self) -> bool {
+        if self.sound_on {
+            self.sound_on = false;
+            self.backend.set_sound(false);
+        }
+        let alive = self.poll_backend();
+        self.backend.render(self.display.pixels(), &self.palette);
+        alive
+    }
+
+    /// Update the keypad and execute pending commands, returns false once the user asked to quit
+    fn poll_backend(&mut self) -> bool {
+        self.backend.poll_input(&mut self.keypad);
+        if self.backend.quit_requested() {
+            self.quit_requested = true;
+            return false;
+        }
+
+        while let Some(command) = self.backend.poll_command() {
+            let result = self.exec_command(command);
+            self.backend.command_done(command, result);
+        }
+        true
+    }
+
+    fn exec_command(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::SaveState(slot) => self.save_slot(slot).map_err(|err| err.to_string()),
+            Command::LoadState(_) | Command::Rewind if self.movie.is_some() =>
+                Err("Not available while recording or playing a movie".to_string()),
+            Command::LoadState(slot) => self.load_slot(slot).map_err(|err| err.to_string()),
+            Command::Rewind => {
+                self.rewinding = true;
+                match self.rewind() {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err("Nothing left to rewind".to_string()),
+                    Err(err) => Err(err.to_string()),
                 }
-                self.regs.dt -= 1;
             }
+            Command::Break => {
+                self.debugger.set_break(BreakReason::Interrupted);
+                Ok(())
+            }
+        }
+    }
 
-            if let Some(check_input) = &mut self.check_input {
-                check_input(&mut self.running, &mut self.keypad);
+    /// Execute one 60Hz frame: the instructions due at the CPU frequency followed by a timer tick.
+    /// The resulting state is recorded in the rewind buffer if enabled.
+    /// While a movie is played back, its keys replace the keypad for the frame.
+    /// The frame is cut short, without ticking the timers, when a breakpoint is hit.
+    /// Does not poll input, render or sleep.
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        if let Some(movie) = &self.movie {
+            movie.begin_frame(&mut self.keypad);
+        }
+        match self.timing {
+            Timing::Fixed => {
+                // in u64, the frequency may be up to u32::MAX
+                let cycles = self.cpu_frequency as u64 + self.cycle_remainder as u64;
+                self.cycle_remainder = (cycles % FRAME_FREQUENCY as u64) as u32;
+                self.run_cycles((cycles / FRAME_FREQUENCY as u64) as u32)?;
+            }
+            Timing::CosmacVip => {
+                while self.frame_cycles < VIP_CPU_CYCLES_PER_FRAME && self.step_debugged()? {}
             }
+        }
+        if self.debugger.break_reason().is_some() {
+            return Ok(());
+        }
+        // the interrupt: cycles past the end of the frame are taken from the next one
+        self.frame_cycles = self.frame_cycles.saturating_sub(VIP_CPU_CYCLES_PER_FRAME);
+        self.tick_timers();
+        let hash = match &self.movie {
+            Some(movie) if movie.needs_hash() => Some(crc32(&self.save_state())),
+            _ => None,
+        };
+        if let Some(movie) = &mut self.movie {
+            movie.end_frame(&self.keypad, hash);
+        }
+        if self.rewind.capacity() > 0 {
+            self.rewind.push(self.save_state());
+        }
+        Ok(())
+    }
 
-            if let Some(render) = &mut self.render {
-                render(self.display.pixels());
+    /// Keep up to `seconds` of per-frame history for `rewind()`, 0 disables rewinding
+    pub fn set_rewind_depth(&mut self, seconds: u32) {
+        self.rewind = RewindBuffer::new((seconds * FRAME_FREQUENCY) as usize);
+    }
+
+    /// Number of frames that can currently be rewound
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.len()
+    }
+
+    /// Approximate memory used by the rewind history, in bytes
+    pub fn rewind_size(&self) -> usize {
+        self.rewind.size()
+    }
+
+    /// Restore the state of the previous frame, returns false if the history is empty
+    pub fn rewind(&mut self) -> Result<bool, StateError> {
+        match self.rewind.pop() {
+            Some(state) => {
+                self.load_state(&state)?;
+                Ok(true)
             }
-        } // end while(running)
+            None => Ok(false),
+        }
+    }
 
-        return Ok(());
+    /// Execute `n` instructions without ticking the timers.
+    /// Stops before an instruction with a breakpoint, or after an instruction
+    /// triggering a watchpoint or register breakpoint, see `break_reason()`.
+    pub fn run_cycles(&mut self, n: u32) -> Result<(), EmulatorError> {
+        for _ in 0..n {
+            if !self.step_debugged()? {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute one instruction unless the program exited or a breakpoint is set on it,
+    /// then check watchpoints and register breakpoints. Returns false if nothing was executed.
+    fn step_debugged(&mut self) -> Result<bool, EmulatorError> {
+        if self.exited || self.debugger.check(&self.regs, &self.memory) {
+            return Ok(false);
+        }
+        if self.debugger.watches_registers() {
+            let before = self.regs.clone();
+            self.step()?;
+            self.debugger.check_registers(&before, &self.regs);
+        } else {
+            self.step()?;
+        }
+        Ok(true)
+    }
+
+    /// Decrement the delay and sound timers, called at 60Hz
+    fn tick_timers(&mut self) {
+        self.vblank = true;
+
+        if self.regs.st > 0 {
+            self.regs.st -= 1;
+        }
+
+        if self.regs.dt > 0 {
+            self.regs.dt -= 1;
+        }
+
+        self.audio.set_playing(self.regs.st > 0);
     }
 
     pub fn stop(&mut self) {
         self.running = false;
     }
 
-    fn exec_instr(&mut self, instr: u16) {
+    /// Returns true once the program executed 00FD - EXIT
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    /// Returns true once the user asked the backend to quit, e.g. by closing the window
+    pub fn quit_requested(&self) -> bool {
+        self.quit_requested
+    }
+
+    /// Current display resolution as (width, height)
+    pub fn display_size(&self) -> (usize, usize) {
+        (self.display.width(), self.display.height())
+    }
+
+    /// Fetch and execute the instruction at PC, ignoring breakpoints. Returns the cycles consumed:
+    /// machine cycles with `Timing::CosmacVip`, 1 with `Timing::Fixed`.
+    /// On error, PC is left pointing at the faulting instruction and the trace, if any, is dumped.
+    pub fn step(&mut self) -> Result<u32, EmulatorError> {
+        let pc = self.regs.pc;
+        let addr = pc as usize;
+        let result = if addr + 1 >= self.memory.len() {
+            Err(EmulatorError::MemoryOutOfBounds { pc, instr: 0, addr: addr + 1 })
+        } else {
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&self.regs, &self.memory);
+            }
+            let instr = self.read_instr(pc);
+            let vx = self.regs.v[(instr >> 8 & 0xF) as usize];
+            self.exec_instr(instr).map(|_| self.instr_cycles(instr, pc, vx))
+        };
+
+        if let Err(ref err) = result {
+            self.regs.pc = pc;
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.dump(err);
+            }
+        }
+        result
+    }
+
+    /// Cycles consumed by `instr` executed at `pc`, `vx` being the value of Vx before execution
+    fn instr_cycles(&mut self, instr: u16, pc: u16, vx: u8) -> u32 {
+        match self.timing {
+            Timing::Fixed => 1,
+            Timing::CosmacVip => {
+                let cycles = if instr >> 12 == 0xD && self.regs.pc == pc {
+                    // waiting for the vertical blank interrupt: the rest of the frame
+                    VIP_CPU_CYCLES_PER_FRAME.saturating_sub(self.frame_cycles)
+                } else {
+                    vip_cycles(instr, vx, self.regs.pc != pc.wrapping_add(INSTR_SIZE))
+                };
+                self.frame_cycles += cycles;
+                cycles
+            }
+        }
+    }
+
+    /// Instruction word at `addr`, 0 past the end of memory
+    pub fn read_instr(&self, addr: u16) -> u16 {
+        let addr = addr as usize;
+        if addr + 1 >= self.memory.len() {
+            return 0;
+        }
+        ((self.memory[addr] as u16) << 8) | (self.memory[addr + 1] as u16)
+    }
+
+    /// Read a byte of memory on behalf of the program, reported to the debugger
+    fn load(&mut self, addr: usize) -> u8 {
+        let value = self.memory[addr];
+        self.debugger.memory_access(Access::Read, addr, value);
+        value
+    }
+
+    /// Write a byte of memory on behalf of the program, reported to the debugger
+    fn store(&mut self, addr: usize, value: u8) {
+        self.memory[addr] = value;
+        self.debugger.memory_access(Access::Write, addr, value);
+    }
+
+    /// `len` bytes of memory starting at `addr`, read with `load()`
+    fn load_bytes(&mut self, addr: usize, len: usize) -> Vec<u8> {
+        (addr..addr + len).map(|addr| self.load(addr)).collect()
+    }
+
+    /// Check that the `len` bytes starting at `start` are inside memory
+    fn check_mem(&self, start: usize, len: usize, pc: u16, instr: u16) -> Result<(), EmulatorError> {
+        if start.checked_add(len).is_none_or(|end| end > self.memory.len()) {
+            let addr = start.max(self.memory.len());
+            return Err(EmulatorError::MemoryOutOfBounds { pc, instr, addr });
+        }
+        Ok(())
+    }
+
+    /// Skip the instruction at PC, F000 nnnn - LD I, long addr - is 4 bytes long
+    fn skip_next_instr(&mut self) {
+        let addr = self.regs.pc as usize;
+        let long = addr + 1 < self.memory.len() && self.memory[addr] == 0xF0 && self.memory[addr + 1] == 0x00;
+        let size = if long { 2 * INSTR_SIZE } else { INSTR_SIZE };
+        self.regs.pc = self.regs.pc.wrapping_add(size);
+    }
+
+    /// Update I after Fx55/Fx65 according to quirks.memory_increment
+    fn increment_i_after_load_store(&mut self, x: usize) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::None => {}
+            MemoryIncrement::X => self.regs.i += x,
+            MemoryIncrement::XPlusOne => self.regs.i += x + 1,
+        }
+    }
+
+    fn exec_instr(&mut self, instr: u16) -> Result<(), EmulatorError> {
+        let pc = self.regs.pc;
+
         // pc now points to next instruction
-        self.regs.pc += INSTR_SIZE;
+        self.regs.pc = self.regs.pc.wrapping_add(INSTR_SIZE);
 
-        match Opcode::from(instr) {
+        let opcode = Opcode::try_from(instr)
+            .map_err(|instr| EmulatorError::UnknownOpcode { pc, instr })?;
+
+        match opcode {
             // 00E0 - CLS - Clear the display
             Opcode::Imm { op: 0, nnn: 0xE0 } => self.display.clear(),
 
             // 00EE - RET - Return from a subroutine
             Opcode::Imm { op: 0, nnn: 0xEE } => {
                 if self.regs.sp == 0 {
-                    panic!("Stack underflow at instruction {:X}", self.regs.pc - 2)
+                    return Err(EmulatorError::StackUnderflow { pc, instr });
                 }
                 self.regs.sp -= 1;
                 self.regs.pc = self.regs.stack[self.regs.sp];
                 self.regs.stack[self.regs.sp + 1] = 0; // clear stack
             }
 
+            // 00CN - SCD nibble - Scroll display down N lines (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn } if nnn & 0xFF0 == 0x0C0 => self.display.scroll_down((nnn & 0xF) as usize),
+
+            // 00Dn - SCU nibble - Scroll display up N lines (XO-CHIP)
+            Opcode::Imm { op: 0, nnn } if nnn & 0xFF0 == 0x0D0 => self.display.scroll_up((nnn & 0xF) as usize),
+
+            // 00FB - SCR - Scroll display right 4 pixels (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn: 0xFB } => self.display.scroll_right(4),
+
+            // 00FC - SCL - Scroll display left 4 pixels (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn: 0xFC } => self.display.scroll_left(4),
+
+            // 00FD - EXIT - Exit the interpreter (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn: 0xFD } => {
+                self.regs.pc = self.regs.pc.wrapping_sub(INSTR_SIZE);
+                self.exited = true;
+                self.running = false;
+            }
+
+            // 00FE - LOW - Disable high resolution mode (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn: 0xFE } => self.display.set_hires(false),
+
+            // 00FF - HIGH - Enable 128x64 high resolution mode (SUPER-CHIP)
+            Opcode::Imm { op: 0, nnn: 0xFF } => self.display.set_hires(true),
+
             // 1nnn - JP addr - Jump to location nnn
             Opcode::Imm { op: 1, nnn } => self.regs.pc = nnn,
 
             // 2nnn - CALL addr - Call subroutine at nnn
             Opcode::Imm { op: 2, nnn } => {
-                self.regs.sp += 1;
-                if self.regs.sp >= self.regs.stack.len() {
-                    panic!("Stack overflow at instruction {:X}", self.regs.pc - 2)
+                if self.regs.sp + 1 >= self.regs.stack.len() {
+                    return Err(EmulatorError::StackOverflow { pc, instr });
                 }
+                self.regs.sp += 1;
                 self.regs.stack[self.regs.sp - 1] = self.regs.pc;
                 self.regs.pc = nnn;
             }
@@ -181,21 +734,39 @@ impl Chip8<'_> {
             // 3xkk - SE Vx, byte - Skip next instruction if Vx = kk
             Opcode::RegImm { op: 3, x, kk } =>
                 if self.regs.v[x] == kk {
-                    self.regs.pc += INSTR_SIZE;
+                    self.skip_next_instr();
                 }
 
             // 4xkk - SNE Vx, byte - Skip next instruction if Vx != kk
             Opcode::RegImm { op: 4, x, kk } =>
                 if self.regs.v[x] != kk {
-                    self.regs.pc += INSTR_SIZE;
+                    self.skip_next_instr();
                 }
 
             // 5xy0 - SE Vx, Vy - Skip next instruction if Vx = Vy
             Opcode::RegReg { op: 5, x, y, op2: 0 } =>
                 if self.regs.v[x] == self.regs.v[y] {
-                    self.regs.pc += INSTR_SIZE;
+                    self.skip_next_instr();
                 }
 
+            // 5xy2 - LD [I], Vx-Vy - Store Vx through Vy in memory starting at I, I is unchanged (XO-CHIP)
+            Opcode::RegReg { op: 5, x, y, op2: 2 } => {
+                let (first, last) = (x.min(y), x.max(y));
+                self.check_mem(self.regs.i, last - first + 1, pc, instr)?;
+                for (offset, reg) in reg_range(x, y).enumerate() {
+                    self.store(self.regs.i + offset, self.regs.v[reg]);
+                }
+            }
+
+            // 5xy3 - LD Vx-Vy, [I] - Read Vx through Vy from memory starting at I, I is unchanged (XO-CHIP)
+            Opcode::RegReg { op: 5, x, y, op2: 3 } => {
+                let (first, last) = (x.min(y), x.max(y));
+                self.check_mem(self.regs.i, last - first + 1, pc, instr)?;
+                for (offset, reg) in reg_range(x, y).enumerate() {
+                    self.regs.v[reg] = self.load(self.regs.i + offset);
+                }
+            }
+
             // 6xkk - LD Vx, byte - Set Vx = kk
             Opcode::RegImm { op: 6, x, kk } => self.regs.v[x] = kk,
 
@@ -206,13 +777,28 @@ impl Chip8<'_> {
             Opcode::RegReg { op: 8, x, y, op2: 0 } => self.regs.v[x] = self.regs.v[y],
 
             // 8xy1 - OR Vx, Vy  - Set Vx = Vx OR Vy
-            Opcode::RegReg { op: 8, x, y, op2: 1 } => self.regs.v[x] |= self.regs.v[y],
+            Opcode::RegReg { op: 8, x, y, op2: 1 } => {
+                self.regs.v[x] |= self.regs.v[y];
+                if self.quirks.vf_reset {
+                    self.regs.v[0xF] = 0;
+                }
+            }
 
             // 8xy2 - AND Vx, Vy - Set Vx = Vx AND Vy
-            Opcode::RegReg { op: 8, x, y, op2: 2 } => self.regs.v[x] &= self.regs.v[y],
+            Opcode::RegReg { op: 8, x, y, op2: 2 } => {
+                self.regs.v[x] &= self.regs.v[y];
+                if self.quirks.vf_reset {
+                    self.regs.v[0xF] = 0;
+                }
+            }
 
             // 8xy3 - XOR Vx, Vy - Set Vx = Vx XOR Vy
-            Opcode::RegReg { op: 8, x, y, op2: 3 } => self.regs.v[x] ^= self.regs.v[y],
+            Opcode::RegReg { op: 8, x, y, op2: 3 } => {
+                self.regs.v[x] ^= self.regs.v[y];
+                if self.quirks.vf_reset {
+                    self.regs.v[0xF] = 0;
+                }
+            }
 
             // 8xy4 - ADD Vx, Vy - Set Vx = Vx + Vy - set VF = carry
             Opcode::RegReg { op: 8, x, y, op2: 4 } => {
@@ -230,7 +816,7 @@ impl Chip8<'_> {
 
             // 8xy6 - SHR Vx {, Vy} - Set Vx = Vx SHR 1
             Opcode::RegReg { op: 8, x, y, op2: 6 } => {
-                if self.legacy_mode {
+                if self.quirks.shift_uses_vy {
                     self.regs.v[0xF] = self.regs.v[y] & 1;
                     self.regs.v[x] = self.regs.v[y] >> 1;
                 } else {
@@ -248,7 +834,7 @@ impl Chip8<'_> {
 
             // 8xyE - SHL Vx {, Vy} - Set Vx = Vx SHL 1
             Opcode::RegReg { op: 8, x, y, op2: 0xE } => {
-                if self.legacy_mode {
+                if self.quirks.shift_uses_vy {
                     self.regs.v[0xF] = (self.regs.v[y] >> 7) & 1;
                     self.regs.v[x] = self.regs.v[y] << 1;
                 } else {
@@ -260,59 +846,97 @@ impl Chip8<'_> {
             // 9xy0 - SNE Vx, Vy - Skip next instruction if Vx != Vy
             Opcode::RegReg { op: 9, x, y, op2: 0 } => {
                 if self.regs.v[x] != self.regs.v[y] {
-                    self.regs.pc += INSTR_SIZE;
+                    self.skip_next_instr();
                 }
             }
 
             // Annn - LD I, addr - Set I = nnn
             Opcode::Imm { op: 0xA, nnn } => self.regs.i = nnn as usize,
 
-            // Bnnn - JP V0, addr - Jump to location nnn + V0 //TODO: overflow?
-            Opcode::Imm { op: 0xB, nnn } => self.regs.pc = nnn + self.regs.v[0] as u16,
+            // Bnnn - JP V0, addr - Jump to location nnn + V0
+            // BXNN - JP Vx, addr - Jump to location xnn + Vx (quirks.jump_uses_vx)
+            Opcode::Imm { op: 0xB, nnn } => {
+                let offset_reg = if self.quirks.jump_uses_vx { (nnn >> 8) as usize } else { 0 };
+                self.regs.pc = nnn + self.regs.v[offset_reg] as u16;
+            }
 
             // Cxkk - RND Vx, byte - Set Vx = random byte AND kk
-            Opcode::RegImm { op: 0xC, x, kk } => self.regs.v[x] = rand::random::<u8>() & kk,
+            Opcode::RegImm { op: 0xC, x, kk } => self.regs.v[x] = self.rng.next_u8(&self.memory) & kk,
 
             // Dxyn - DRW Vx, Vy, nibble
             // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision
+            // Dxy0 - DRW Vx, Vy, 0 - Display 16x16 sprite, 32 bytes starting at I (SUPER-CHIP)
             Opcode::RegReg { op: 0xD, x, y, op2: n } => {
-                let mut sprite_bytes = Vec::new();
-                for i in 0..n {
-                    let byte = self.memory[self.regs.i + i as usize];
-                    sprite_bytes.push(byte);
+                if self.quirks.display_wait || self.timing == Timing::CosmacVip {
+                    if !self.vblank {
+                        // wait for the vertical blank interrupt
+                        self.regs.pc = self.regs.pc.wrapping_sub(INSTR_SIZE);
+                        return Ok(());
+                    }
+                    self.vblank = false;
                 }
 
-                let sprite = Sprite::new(sprite_bytes);
-                let collision = self.display.draw_sprite(&sprite, self.regs.v[x] as usize, self.regs.v[y] as usize);
+                // XO-CHIP: one sprite per selected plane, stored one after the other
+                let len = if n == 0 { 32 } else { n as usize };
+                let planes = self.display.planes();
+                self.check_mem(self.regs.i, len * planes.count_ones() as usize, pc, instr)?;
+
+                let mut collision = false;
+                let mut addr = self.regs.i;
+                for plane in [1, 2].iter().filter(|plane| planes & *plane != 0) {
+                    let sprite_bytes = self.load_bytes(addr, len);
+                    addr += len;
+
+                    let sprite = if n == 0 { Sprite::new_large(sprite_bytes) } else { Sprite::new(sprite_bytes) };
+                    collision |= self.display.draw_sprite(&sprite, self.regs.v[x] as usize, self.regs.v[y] as usize,
+                                                          self.quirks.clip_sprites, *plane);
+                }
                 self.regs.v[0xF] = collision as u8;
             }
 
             // Ex9E - SKP Vx - Skip next instruction if key with the value of Vx is pressed
             // ExA1 - SKNP Vx - Skip next instruction if key with the value of Vx is not pressed
             Opcode::RegImm { op: 0xE, x, kk } if kk == 0x9E || kk == 0xA1 => {
-                let key = self.regs.v[x] as usize;
+                let key = self.regs.v[x];
                 if key > 0xF {
-                    panic!("instruction {:X} executed with Vx ({:X}) > 0xF", instr, key);
+                    return Err(EmulatorError::InvalidKey { pc, instr, key });
                 }
 
-                let pressed = self.keypad[key];
+                let pressed = self.keypad[key as usize];
 
                 if (kk == 0x9E && pressed) || (kk == 0xA1 && !pressed) {
-                    self.regs.pc += 2;
+                    self.skip_next_instr();
                 }
             }
 
+            // F000 nnnn - LD I, long addr - Set I = nnnn, the next 16-bit word (XO-CHIP)
+            Opcode::RegImm { op: 0xF, x: 0, kk: 0x00 } => {
+                let addr = self.regs.pc as usize;
+                self.check_mem(addr, 2, pc, instr)?;
+                self.regs.i = ((self.load(addr) as usize) << 8) | self.load(addr + 1) as usize;
+                self.regs.pc = self.regs.pc.wrapping_add(INSTR_SIZE);
+            }
+
+            // F002 - AUDIO - Load the 16-byte audio pattern starting at I (XO-CHIP)
+            Opcode::RegImm { op: 0xF, x: 0, kk: 0x02 } => {
+                self.check_mem(self.regs.i, AUDIO_PATTERN_SIZE, pc, instr)?;
+                let pattern = self.load_bytes(self.regs.i, AUDIO_PATTERN_SIZE);
+                self.audio.set_pattern(&pattern);
+            }
+
+            // Fn01 - PLANE n - Select the drawing planes, n is a bitmask (XO-CHIP)
+            Opcode::RegImm { op: 0xF, x: n, kk: 0x01 } if n <= ALL_PLANES as usize =>
+                self.display.select_planes(n as u8),
+
             // Fx07 - LD Vx, DT - Set Vx = delay timer value
             Opcode::RegImm { op: 0xF, x, kk: 0x07 } => self.regs.v[x] = self.regs.dt,
 
             // Fx0A - LD Vx, K - Wait for a key press, store the value of the key in Vx
             Opcode::RegImm { op: 0xF, x, kk: 0x0A } => {
-                let mut key_pressed = self.keypad.iter().position(|k| *k);
-
-                if key_pressed.is_some() {
-                    self.regs.v[x] = (key_pressed.unwrap()) as u8
+                if let Some(key) = self.keypad.iter().position(|k| *k) {
+                    self.regs.v[x] = key as u8
                 } else {
-                    self.regs.pc -= INSTR_SIZE;
+                    self.regs.pc = self.regs.pc.wrapping_sub(INSTR_SIZE);
                 }
             }
 
@@ -330,43 +954,61 @@ impl Chip8<'_> {
             Opcode::RegImm { op: 0xF, x, kk: 0x29 } => {
                 let value = self.regs.v[x];
                 if value > 0xF {
-                    panic!("instr {:X}: Vx {:X} must be a digit not larger than 0xF", instr, value)
+                    return Err(EmulatorError::InvalidDigit { pc, instr, digit: value });
                 }
                 self.regs.i = (value as usize) * 5; /* five bytes per font digit */
             }
 
+            // Fx30 - LD HF, Vx - Set I = location of large sprite for digit Vx (SUPER-CHIP)
+            Opcode::RegImm { op: 0xF, x, kk: 0x30 } => {
+                let value = self.regs.v[x];
+                if value > 0xF {
+                    return Err(EmulatorError::InvalidDigit { pc, instr, digit: value });
+                }
+                self.regs.i = BIG_FONT_ADDR + (value as usize) * 10; /* ten bytes per large font digit */
+            }
+
             // Fx33 - LD B, Vx - Store BCD representation of Vx in memory locations I, I+1, and I+2
             Opcode::RegImm { op: 0xF, x, kk: 0x33 } => {
                 let value = self.regs.v[x];
-                self.memory[self.regs.i + 2] = value % 10;
-                self.memory[self.regs.i + 1] = (value / 10) % 10;
-                self.memory[self.regs.i] = (value / 100) % 10;
+                self.check_mem(self.regs.i, 3, pc, instr)?;
+                self.store(self.regs.i + 2, value % 10);
+                self.store(self.regs.i + 1, (value / 10) % 10);
+                self.store(self.regs.i, (value / 100) % 10);
             }
 
             // Fx55 - LD [I], Vx - Store registers V0 through Vx in memory starting at location I
             Opcode::RegImm { op: 0xF, x, kk: 0x55 } => {
-                for i in 0..(x + 1) {
-                    self.memory[self.regs.i + i] = self.regs.v[i];
+                self.check_mem(self.regs.i, x + 1, pc, instr)?;
+                for reg in 0..=x {
+                    self.store(self.regs.i + reg, self.regs.v[reg]);
                 }
 
-                if self.legacy_mode {
-                    self.regs.i += x + 1;
-                }
+                self.increment_i_after_load_store(x);
             }
 
             // Fx65 - LD Vx, [I] - Read registers V0 through Vx from memory starting at location I
             Opcode::RegImm { op: 0xF, x, kk: 0x65 } => {
-                for i in 0..(x + 1) {
-                    self.regs.v[i] = self.memory[self.regs.i + i];
-                }
+                self.check_mem(self.regs.i, x + 1, pc, instr)?;
+                let values = self.load_bytes(self.regs.i, x + 1);
+                self.regs.v[..=x].copy_from_slice(&values);
 
-                if self.legacy_mode {
-                    self.regs.i += x + 1;
-                }
+                self.increment_i_after_load_store(x);
             }
 
+            // Fx3A - PITCH Vx - Set the audio pattern playback rate (XO-CHIP)
+            Opcode::RegImm { op: 0xF, x, kk: 0x3A } => self.audio.set_pitch(self.regs.v[x]),
+
+            // Fx75 - LD R, Vx - Store V0 through Vx in RPL user flags (SUPER-CHIP)
+            Opcode::RegImm { op: 0xF, x, kk: 0x75 } => self.rpl_flags[..=x].copy_from_slice(&self.regs.v[..=x]),
+
+            // Fx85 - LD Vx, R - Read V0 through Vx from RPL user flags (SUPER-CHIP)
+            Opcode::RegImm { op: 0xF, x, kk: 0x85 } => self.regs.v[..=x].copy_from_slice(&self.rpl_flags[..=x]),
+
             _ =>
-                panic!("Unknown instruction {:X}", instr)
+                return Err(EmulatorError::UnknownOpcode { pc, instr })
         } // end match instr
+
+        Ok(())
     } // end exec_instr
 } // end impl Chip8
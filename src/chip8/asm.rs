@@ -0,0 +1,698 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{Instruction, MEM_SIZE, PROGRAM_START};
+
+/// Includes nested deeper than this are rejected, which also catches include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Assembly error, located by file, line and column (both 1-based)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Position of a token in the sources
+#[derive(Debug, Clone)]
+struct Pos {
+    /// index in Assembler::files
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    Str(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// Parse a number: decimal, `#`, `$` or `0x` hexadecimal, `%` or `0b` binary
+pub fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix('#').or_else(|| lower.strip_prefix('$'))
+        .or_else(|| lower.strip_prefix("0x")) {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix('%').or_else(|| lower.strip_prefix("0b")) {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Split a line into tokens with their 1-based column, stopping at a `;` comment
+fn tokenize(line: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = line.chars().collect();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+        if c == ';' {
+            break;
+        } else if c.is_whitespace() {
+            pos += 1;
+        } else if c == '"' {
+            let end = chars[pos + 1..].iter().position(|c| *c == '"')
+                .ok_or((column, "Unterminated string".to_string()))?;
+            tokens.push((Token::Str(chars[pos + 1..pos + 1 + end].iter().collect()), column));
+            pos += end + 2;
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '#' || c == '$' || c == '%' {
+            let len = chars[pos + 1..].iter()
+                .position(|c| !c.is_ascii_alphanumeric() && *c != '_' && *c != '.')
+                .unwrap_or(chars.len() - pos - 1) + 1;
+            let word: String = chars[pos..pos + len].iter().collect();
+            let token = if c.is_ascii_digit() || c == '#' || c == '$' || c == '%' {
+                Token::Number(parse_number(&word).ok_or((column, format!("Invalid number {}", word)))?)
+            } else {
+                Token::Ident(word)
+            };
+            tokens.push((token, column));
+            pos += len;
+        } else if ",:=[]+-()".contains(c) {
+            tokens.push((Token::Symbol(c), column));
+            pos += 1;
+        } else {
+            return Err((column, format!("Unexpected character {}", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Sum of terms, evaluated once all labels are known
+#[derive(Debug, Clone)]
+struct Expr {
+    /// (negated, term, column)
+    terms: Vec<(bool, Term, usize)>,
+    column: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    V(u8),
+    /// Vx-Vy
+    VRange(u8, u8),
+    I,
+    /// [I]
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    /// LONG nnnn
+    Long(Expr),
+    Value(Expr),
+}
+
+/// Register index of `v0`-`vf`, in any case
+pub fn register(name: &str) -> Option<u8> {
+    let lower = name.to_ascii_lowercase();
+    match lower.strip_prefix('v') {
+        Some(x) if x.len() == 1 => u8::from_str_radix(x, 16).ok(),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+/// Statement placed at `addr`, encoded by the second pass
+struct Placed {
+    addr: usize,
+    statement: Statement,
+    pos: Pos,
+}
+
+/// Tokens of one line being parsed
+struct LineParser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    /// column reported at the end of the line
+    end: usize,
+}
+
+impl LineParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, column)| *column)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn accept(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn error(&self, message: String) -> (usize, String) {
+        (self.column(), message)
+    }
+
+    fn unexpected(&self, expected: &str) -> (usize, String) {
+        match self.peek() {
+            Some(token) => self.error(format!("Unexpected {}, expected {}", token, expected)),
+            None => self.error(format!("Unexpected end of line, expected {}", expected)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, (usize, String)> {
+        let column = self.column();
+        let mut terms = Vec::new();
+        let mut negated = self.accept('-');
+        loop {
+            let term_column = self.column();
+            let term = match self.next() {
+                Some(Token::Number(value)) => Term::Number(value),
+                Some(Token::Ident(name)) => Term::Symbol(name),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected("a number or a label"));
+                }
+            };
+            terms.push((negated, term, term_column));
+            if self.accept('+') {
+                negated = false;
+            } else if self.accept('-') {
+                negated = true;
+            } else {
+                return Ok(Expr { terms, column });
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, (usize, String)> {
+        if self.accept('[') {
+            match self.next() {
+                Some(Token::Ident(ref name)) if name.eq_ignore_ascii_case("i") => {}
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.unexpected("I"));
+                }
+            }
+            if !self.accept(']') {
+                return Err(self.unexpected("]"));
+            }
+            return Ok(Operand::IndirectI);
+        }
+
+        let name = match self.peek() {
+            Some(Token::Ident(name)) => name.to_ascii_uppercase(),
+            _ => return Ok(Operand::Value(self.expr()?)),
+        };
+        if let Some(x) = register(&name) {
+            self.pos += 1;
+            if self.accept('-') {
+                match self.next() {
+                    Some(Token::Ident(ref name)) if register(name).is_some() =>
+                        return Ok(Operand::VRange(x, register(name).unwrap())),
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.unexpected("a register"));
+                    }
+                }
+            }
+            return Ok(Operand::V(x));
+        }
+
+        let keyword = match name.as_str() {
+            "I" => Operand::I,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "R" => Operand::R,
+            "LONG" => {
+                self.pos += 1;
+                return Ok(Operand::Long(self.expr()?));
+            }
+            _ => return Ok(Operand::Value(self.expr()?)),
+        };
+        self.pos += 1;
+        Ok(keyword)
+    }
+
+    /// Comma-separated list until the end of the line
+    fn list<T>(&mut self, item: fn(&mut LineParser) -> Result<T, (usize, String)>) -> Result<Vec<T>, (usize, String)> {
+        let mut items = Vec::new();
+        if self.at_end() {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.at_end() {
+                return Ok(items);
+            }
+            if !self.accept(',') {
+                return Err(self.unexpected(","));
+            }
+        }
+    }
+
+    /// db operand: an expression or a string, one byte per character
+    fn byte_items(&mut self) -> Result<Vec<Expr>, (usize, String)> {
+        if let Some(Token::Str(s)) = self.peek().cloned() {
+            let column = self.column();
+            self.pos += 1;
+            return Ok(s.bytes()
+                .map(|byte| Expr { terms: vec![(false, Term::Number(byte as i64), column)], column })
+                .collect());
+        }
+        Ok(vec![self.expr()?])
+    }
+}
+
+/// Two-pass assembler: the first pass places statements and defines labels,
+/// the second pass evaluates operands and encodes them
+struct Assembler {
+    files: Vec<String>,
+    symbols: HashMap<String, i64>,
+    placed: Vec<Placed>,
+    addr: usize,
+}
+
+impl Assembler {
+    fn error(&self, pos: &Pos, column: usize, message: String) -> AsmError {
+        AsmError { file: self.files[pos.file].clone(), line: pos.line, column, message }
+    }
+
+    fn define(&mut self, name: &str, value: i64, pos: &Pos) -> Result<(), AsmError> {
+        if register(name).is_some() {
+            return Err(self.error(pos, pos.column, format!("{} is a register name", name)));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(self.error(pos, pos.column, format!("{} is already defined", name)));
+        }
+        Ok(())
+    }
+
+    fn eval(&self, expr: &Expr, pos: &Pos) -> Result<i64, AsmError> {
+        let mut value = 0i64;
+        for (negated, term, column) in &expr.terms {
+            let term = match term {
+                Term::Number(value) => *value,
+                Term::Symbol(name) => *self.symbols.get(name)
+                    .ok_or_else(|| self.error(pos, *column, format!("Undefined symbol {}", name)))?,
+            };
+            value = if *negated { value.checked_sub(term) } else { value.checked_add(term) }
+                .ok_or_else(|| self.error(pos, *column, "Value out of range".to_string()))?;
+        }
+        Ok(value)
+    }
+
+    /// Evaluate `expr` and check it fits in `max`, negative bytes are accepted as two's complement
+    fn eval_max(&self, expr: &Expr, pos: &Pos, max: i64) -> Result<u16, AsmError> {
+        let value = self.eval(expr, pos)?;
+        let value = if max == 0xFF && (-128..0).contains(&value) { value + 0x100 } else { value };
+        if value < 0 || value > max {
+            return Err(self.error(pos, expr.column, format!("Value {:#X} out of range (0-{:#X})", value, max)));
+        }
+        Ok(value as u16)
+    }
+
+    fn assemble_file(&mut self, path: &Path, depth: usize, include_pos: Option<&Pos>) -> Result<(), AsmError> {
+        let source = fs::read_to_string(path).map_err(|err| match include_pos {
+            Some(pos) => self.error(pos, pos.column, format!("Cannot include {}: {}", path.display(), err)),
+            None => AsmError { file: path.display().to_string(), line: 0, column: 0, message: err.to_string() },
+        })?;
+        self.assemble_source(&source, &path.display().to_string(), path.parent(), depth)
+    }
+
+    /// First pass over one source file, includes are resolved relative to `dir`
+    fn assemble_source(&mut self, source: &str, name: &str, dir: Option<&Path>, depth: usize) -> Result<(), AsmError> {
+        self.files.push(name.to_string());
+        let file = self.files.len() - 1;
+
+        for (index, line) in source.lines().enumerate() {
+            let mut pos = Pos { file, line: index + 1, column: 1 };
+            let tokens = tokenize(line).map_err(|(column, message)| self.error(&pos, column, message))?;
+            let mut parser = LineParser { tokens, pos: 0, end: line.chars().count() + 1 };
+            self.first_pass_line(&mut parser, &mut pos, dir, depth)
+                .map_err(|err| err.unwrap_or_else(|(column, message)| self.error(&pos, column, message)))?;
+        }
+        Ok(())
+    }
+
+    /// Errors are either already located (included files) or a column and message in this line
+    fn first_pass_line(&mut self, parser: &mut LineParser, pos: &mut Pos, dir: Option<&Path>,
+                       depth: usize) -> Result<(), Result<AsmError, (usize, String)>> {
+        // label:
+        if let (Some((Token::Ident(name), column)), Some((Token::Symbol(':'), _))) =
+            (parser.tokens.first().cloned(), parser.tokens.get(1)) {
+            pos.column = column;
+            self.define(&name, self.addr as i64, pos).map_err(Ok)?;
+            parser.pos = 2;
+        }
+
+        let (word, column) = match parser.tokens.get(parser.pos).cloned() {
+            Some((Token::Ident(word), column)) => (word, column),
+            None => return Ok(()),
+            _ => return Err(Err(parser.unexpected("a mnemonic or a directive"))),
+        };
+        pos.column = column;
+        parser.pos += 1;
+
+        // name = value / name EQU value
+        let is_equ = matches!(parser.peek(), Some(Token::Ident(s)) if s.eq_ignore_ascii_case("equ"));
+        if parser.accept('=') || is_equ {
+            if is_equ {
+                parser.pos += 1;
+            }
+            let expr = parser.expr().map_err(Err)?;
+            let value = self.eval(&expr, pos).map_err(Ok)?;
+            self.define(&word, value, pos).map_err(Ok)?;
+            return self.end_of_line(parser);
+        }
+
+        let statement = match word.to_ascii_lowercase().as_str() {
+            "include" => {
+                let file = match parser.next() {
+                    Some(Token::Str(file)) => file,
+                    _ => {
+                        parser.pos -= 1;
+                        return Err(Err(parser.unexpected("a file name in quotes")));
+                    }
+                };
+                self.end_of_line(parser)?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(Err((column, "Includes nested too deeply".to_string())));
+                }
+                let path = dir.map_or_else(|| PathBuf::from(&file), |dir| dir.join(&file));
+                return self.assemble_file(&path, depth + 1, Some(pos)).map_err(Ok);
+            }
+            "org" => {
+                let expr = parser.expr().map_err(Err)?;
+                let addr = self.eval(&expr, pos).map_err(Ok)? as usize;
+                if addr < self.addr || addr > MEM_SIZE {
+                    return Err(Err((expr.column, format!("Cannot move the address back to {:#X}", addr))));
+                }
+                self.addr = addr;
+                return self.end_of_line(parser);
+            }
+            "db" => {
+                let bytes: Vec<Expr> = parser.list(LineParser::byte_items).map_err(Err)?.into_iter().flatten().collect();
+                Statement::Bytes(bytes)
+            }
+            "dw" => Statement::Words(parser.list(LineParser::expr).map_err(Err)?),
+            _ => Statement::Instruction {
+                mnemonic: word.to_ascii_uppercase(),
+                operands: parser.list(LineParser::operand).map_err(Err)?,
+            },
+        };
+
+        let size = match &statement {
+            Statement::Bytes(bytes) => bytes.len(),
+            Statement::Words(words) => 2 * words.len(),
+            Statement::Instruction { operands, .. } if operands.iter().any(|op| matches!(op, Operand::Long(_))) => 4,
+            Statement::Instruction { .. } => 2,
+        };
+        self.placed.push(Placed { addr: self.addr, statement, pos: pos.clone() });
+        self.addr += size;
+        Ok(())
+    }
+
+    fn end_of_line(&self, parser: &LineParser) -> Result<(), Result<AsmError, (usize, String)>> {
+        if !parser.at_end() {
+            return Err(Err(parser.unexpected("end of line")));
+        }
+        Ok(())
+    }
+
+    /// Second pass, returns the image loaded at PROGRAM_START
+    fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        let end = self.addr;
+        if end > MEM_SIZE {
+            return Err(AsmError { file: self.files[0].clone(), line: 0, column: 0,
+                                  message: format!("Program too large, ends at {:#X}", end) });
+        }
+        let mut image = vec![0; end.saturating_sub(PROGRAM_START)];
+
+        for placed in &self.placed {
+            let pos = &placed.pos;
+            let mut bytes = Vec::new();
+            match &placed.statement {
+                Statement::Bytes(values) => for value in values {
+                    bytes.push(self.eval_max(value, pos, 0xFF)? as u8);
+                }
+                Statement::Words(values) => for value in values {
+                    bytes.extend_from_slice(&self.eval_max(value, pos, 0xFFFF)?.to_be_bytes());
+                }
+                Statement::Instruction { mnemonic, operands } => {
+                    let instr = self.instruction(mnemonic, operands, pos)?;
+                    let (word, next) = instr.encode();
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                    if let Some(next) = next {
+                        bytes.extend_from_slice(&next.to_be_bytes());
+                    }
+                }
+            }
+            if placed.addr < PROGRAM_START {
+                return Err(self.error(pos, pos.column, format!("Address {:#X} is below {:#X}", placed.addr, PROGRAM_START)));
+            }
+            let offset = placed.addr - PROGRAM_START;
+            image[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        }
+        Ok(image)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], pos: &Pos) -> Result<Instruction, AsmError> {
+        use Operand::*;
+
+        let addr = |expr: &Expr| self.eval_max(expr, pos, 0xFFF);
+        let byte = |expr: &Expr| self.eval_max(expr, pos, 0xFF).map(|value| value as u8);
+        let nibble = |expr: &Expr| self.eval_max(expr, pos, 0xF).map(|value| value as u8);
+
+        let instr = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n)]) => Instruction::Scd { n: nibble(n)? },
+            ("SCU", [Value(n)]) => Instruction::Scu { n: nibble(n)? },
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("SYS", [Value(nnn)]) => Instruction::Sys { nnn: addr(nnn)? },
+            ("JP", [Value(nnn)]) => Instruction::Jp { nnn: addr(nnn)? },
+            ("JP", [V(0), Value(nnn)]) => Instruction::JpV0 { nnn: addr(nnn)? },
+            ("CALL", [Value(nnn)]) => Instruction::Call { nnn: addr(nnn)? },
+            ("SE", [V(x), V(y)]) => Instruction::SeReg { x: *x, y: *y },
+            ("SE", [V(x), Value(kk)]) => Instruction::SeImm { x: *x, kk: byte(kk)? },
+            ("SNE", [V(x), V(y)]) => Instruction::SneReg { x: *x, y: *y },
+            ("SNE", [V(x), Value(kk)]) => Instruction::SneImm { x: *x, kk: byte(kk)? },
+            ("LD", [V(x), V(y)]) => Instruction::LdReg { x: *x, y: *y },
+            ("LD", [V(x), Value(kk)]) => Instruction::LdImm { x: *x, kk: byte(kk)? },
+            ("LD", [I, Value(nnn)]) => Instruction::LdI { nnn: addr(nnn)? },
+            ("LD", [I, Long(nnnn)]) => Instruction::LdILong { nnnn: self.eval_max(nnnn, pos, 0xFFFF)? },
+            ("LD", [V(x), Dt]) => Instruction::LdVxDt { x: *x },
+            ("LD", [V(x), K]) => Instruction::LdVxK { x: *x },
+            ("LD", [Dt, V(x)]) => Instruction::LdDtVx { x: *x },
+            ("LD", [St, V(x)]) => Instruction::LdStVx { x: *x },
+            ("LD", [F, V(x)]) => Instruction::LdF { x: *x },
+            ("LD", [Hf, V(x)]) => Instruction::LdHf { x: *x },
+            ("LD", [B, V(x)]) => Instruction::LdB { x: *x },
+            ("LD", [IndirectI, V(x)]) => Instruction::Save { x: *x },
+            ("LD", [V(x), IndirectI]) => Instruction::Load { x: *x },
+            ("LD", [IndirectI, VRange(x, y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LD", [VRange(x, y), IndirectI]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [R, V(x)]) => Instruction::SaveFlags { x: *x },
+            ("LD", [V(x), R]) => Instruction::LoadFlags { x: *x },
+            ("ADD", [V(x), V(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [V(x), Value(kk)]) => Instruction::AddImm { x: *x, kk: byte(kk)? },
+            ("ADD", [I, V(x)]) => Instruction::AddI { x: *x },
+            ("OR", [V(x), V(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [V(x), V(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [V(x), V(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [V(x), V(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [V(x), V(y)]) => Instruction::Subn { x: *x, y: *y },
+            ("SHR", [V(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [V(x), V(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [V(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [V(x), V(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [V(x), Value(kk)]) => Instruction::Rnd { x: *x, kk: byte(kk)? },
+            ("DRW", [V(x), V(y), Value(n)]) => Instruction::Drw { x: *x, y: *y, n: nibble(n)? },
+            ("SKP", [V(x)]) => Instruction::Skp { x: *x },
+            ("SKNP", [V(x)]) => Instruction::Sknp { x: *x },
+            ("AUDIO", []) => Instruction::Audio,
+            ("PLANE", [Value(n)]) => Instruction::Plane { n: self.eval_max(n, pos, 3)? as u8 },
+            ("PITCH", [V(x)]) => Instruction::Pitch { x: *x },
+            _ => return Err(self.error(pos, pos.column, format!("Invalid operands for {}", mnemonic))),
+        };
+        Ok(instr)
+    }
+}
+
+fn new_assembler() -> Assembler {
+    Assembler { files: Vec::new(), symbols: HashMap::new(), placed: Vec::new(), addr: PROGRAM_START }
+}
+
+/// Assemble Cowgod-syntax source into an image loaded at PROGRAM_START, ready for `Chip8::load_rom()`.
+///
+/// Lines hold an optional `label:` followed by an instruction (`DRW V1, V2, 5`) or a directive:
+/// `name = value` or `name EQU value` constants, `db` bytes and strings, `dw` big-endian words,
+/// `org addr` and `include "file"`, resolved from the current directory.
+/// Numbers are decimal, `#`, `$` or `0x` hexadecimal, `%` or `0b` binary and can be summed with labels.
+/// Comments start with `;`.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = new_assembler();
+    assembler.assemble_source(source, "<input>", None, 0)?;
+    assembler.encode()
+}
+
+/// Assemble a source file, includes are resolved relative to its directory
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = new_assembler();
+    assembler.assemble_file(path.as_ref(), 0, None)?;
+    assembler.encode()
+}
+
+#[test]
+fn assemble_program() {
+    let source = "
+; count to ten
+COUNT = 10
+start:  LD V0, 0
+loop:   ADD V0, 1
+        SNE V0, COUNT       ; done?
+        JP done
+        JP loop
+done:   LD I, sprite
+        DRW V0, V1, sprite_end - sprite
+        LD [I], V0-V2
+        LD I, LONG sprite + 1
+        JP V0, start
+sprite: db %11110000, $90, \"A\"
+sprite_end:
+        dw #1234
+";
+    assert_eq!(assemble(source), Ok(vec![
+        0x60, 0x00, 0x70, 0x01, 0x40, 0x0A, 0x12, 0x0A, 0x12, 0x02,
+        0xA2, 0x16, 0xD0, 0x13, 0x50, 0x22, 0xF0, 0x00, 0x02, 0x17, 0xB2, 0x00,
+        0xF0, 0x90, 0x41, 0x12, 0x34,
+    ]));
+}
+
+#[test]
+fn assemble_errors() {
+    let error = |source: &str| assemble(source).unwrap_err().to_string();
+    assert_eq!(error("  CLS\n  JP nowhere"), "<input>:2:6: Undefined symbol nowhere");
+    assert_eq!(error("  LD V0, 256"), "<input>:1:10: Value 0x100 out of range (0-0xFF)");
+    assert_eq!(error("  db 9223372036854775807 + 1"), "<input>:1:28: Value out of range");
+    assert_eq!(error("  db 0 - 9223372036854775807 - 2"), "<input>:1:32: Value out of range");
+    assert_eq!(error("  LD V0, DT, 1"), "<input>:1:3: Invalid operands for LD");
+    assert_eq!(error("a: CLS\na: CLS"), "<input>:2:1: a is already defined");
+    assert_eq!(error("  DRW V0 V1, 1"), "<input>:1:10: Unexpected V1, expected ,");
+    assert_eq!(error("  db \"abc"), "<input>:1:6: Unterminated string");
+    assert!(error("  include \"missing.8s\"").starts_with("<input>:1:3: Cannot include missing.8s"));
+}
+
+#[test]
+fn assemble_include() {
+    let dir = std::env::temp_dir().join(format!("chip8-asm-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.8s"), "  CALL sub\n  include \"sub.8s\"\n").unwrap();
+    fs::write(dir.join("sub.8s"), "sub: RET\n  JP sub\n  LD V0, V1 V2\n").unwrap();
+
+    let err = assemble_file(dir.join("main.8s")).unwrap_err();
+    assert_eq!((err.file.ends_with("sub.8s"), err.line, err.column), (true, 3, 13));
+
+    fs::write(dir.join("sub.8s"), "sub: RET\n  JP sub\n").unwrap();
+    assert_eq!(assemble_file(dir.join("main.8s")), Ok(vec![0x22, 0x02, 0x00, 0xEE, 0x12, 0x02]));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn disassemble_assemble_round_trip() {
+    use crate::chip8::disassemble_rom;
+
+    let roms: [&[u8]; 9] = [
+        include_bytes!("../../roms/Airplane.ch8"),
+        include_bytes!("../../roms/Bowling.ch8"),
+        include_bytes!("../../roms/CAVE.ch8"),
+        include_bytes!("../../roms/PONG.ch8"),
+        include_bytes!("../../roms/Puzzle.ch8"),
+        include_bytes!("../../roms/Space Invaders.ch8"),
+        include_bytes!("../../roms/Tetris.ch8"),
+        include_bytes!("../../roms/Tron.ch8"),
+        include_bytes!("../../roms/test.ch8"),
+    ];
+    for rom in roms.iter() {
+        assert_eq!(assemble(&disassemble_rom(rom)).as_deref(), Ok(*rom));
+    }
+
+    // every instruction word, including SUPER-CHIP and XO-CHIP ones, in 4K word images
+    for high in 0..16u16 {
+        let mut rom = Vec::new();
+        let mut source = String::new();
+        for word in (high << 12)..=(high << 12 | 0xFFF) {
+            let mut instr = Instruction::decode(word);
+            if let Instruction::Unknown(_) = instr {
+                continue;
+            }
+            if let Instruction::LdILong { ref mut nnnn } = instr {
+                *nnnn = 0x1234;
+            }
+            let (word, next) = instr.encode();
+            rom.extend_from_slice(&word.to_be_bytes());
+            rom.extend(next.iter().flat_map(|next| next.to_be_bytes().to_vec()));
+            source.push_str(&format!("    {}\n", instr));
+        }
+        assert_eq!(assemble(&source), Ok(rom));
+    }
+}
@@ -0,0 +1,134 @@
+/// Size in bytes of an XO-CHIP audio pattern
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+
+/// Pitch register value at which patterns play at 4000 bits per second
+const DEFAULT_PITCH: u8 = 64;
+
+/// Square wave at 500Hz (at the default pitch), played until a program loads its own pattern
+const DEFAULT_PATTERN: [u8; AUDIO_PATTERN_SIZE] = [0xF0; AUDIO_PATTERN_SIZE];
+
+/// XO-CHIP audio: a 128-bit 1-bit sample pattern looped at a rate given by the pitch register,
+/// audible while the sound timer is non-zero.
+///
+/// Backends pull samples from it with `fill()`.
+pub struct AudioStream {
+    pattern: [u8; AUDIO_PATTERN_SIZE],
+    /// true once the program loaded a pattern with F002
+    pattern_loaded: bool,
+    pitch: u8,
+    /// gated by the sound timer
+    playing: bool,
+    /// position in the pattern, in bits
+    position: f64,
+}
+
+impl AudioStream {
+    pub fn new() -> AudioStream {
+        AudioStream {
+            pattern: DEFAULT_PATTERN,
+            pattern_loaded: false,
+            pitch: DEFAULT_PITCH,
+            playing: false,
+            position: 0.0,
+        }
+    }
+
+    pub fn pattern(&self) -> &[u8; AUDIO_PATTERN_SIZE] {
+        &self.pattern
+    }
+
+    pub fn set_pattern(&mut self, pattern: &[u8]) {
+        self.pattern.copy_from_slice(pattern);
+        self.pattern_loaded = true;
+    }
+
+    /// Returns true once the program loaded its own pattern
+    pub fn pattern_loaded(&self) -> bool {
+        self.pattern_loaded
+    }
+
+    /// Restore the pattern and pitch registers, e.g. from a save state
+    pub fn restore(&mut self, pattern: &[u8], pattern_loaded: bool, pitch: u8) {
+        self.pattern.copy_from_slice(pattern);
+        self.pattern_loaded = pattern_loaded;
+        self.pitch = pitch;
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_playing(&mut self, playing: bool) {
+        if !playing {
+            self.position = 0.0;
+        }
+        self.playing = playing;
+    }
+
+    /// Pattern playback rate in bits per second: 4000 * 2 ^ ((pitch - 64) / 48)
+    pub fn bit_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    /// Fill `out` with the next samples at `sample_rate` Hz,
+    /// 1.0 for a set pattern bit, -1.0 for a cleared bit and 0.0 when not playing
+    pub fn fill(&mut self, out: &mut [f32], sample_rate: u32) {
+        if !self.playing {
+            out.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+
+        let pattern_bits = (AUDIO_PATTERN_SIZE * 8) as f64;
+        let step = self.bit_rate() / sample_rate as f64;
+        for sample in out.iter_mut() {
+            let bit = self.position as usize;
+            let set = self.pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+            *sample = if set { 1.0 } else { -1.0 };
+            self.position = (self.position + step) % pattern_bits;
+        }
+    }
+}
+
+impl Default for AudioStream {
+    fn default() -> Self {
+        AudioStream::new()
+    }
+}
+
+#[test]
+fn audio_stream_silent_when_not_playing() {
+    let mut audio = AudioStream::new();
+    let mut out = vec![1.0; 8];
+    audio.fill(&mut out, 8000);
+    assert!(out.iter().all(|sample| *sample == 0.0));
+}
+
+#[test]
+fn audio_stream_plays_pattern() {
+    let mut audio = AudioStream::new();
+    let mut pattern = [0; AUDIO_PATTERN_SIZE];
+    pattern[0] = 0b1010_0000;
+    audio.set_pattern(&pattern);
+    audio.set_playing(true);
+    assert_eq!(audio.bit_rate(), 4000.0);
+
+    // one sample per bit
+    let mut out = vec![0.0; 5];
+    audio.fill(&mut out, 4000);
+    assert_eq!(out, vec![1.0, -1.0, 1.0, -1.0, -1.0]);
+
+    // one octave higher, two bits per sample
+    audio.set_pitch(64 + 48);
+    audio.set_playing(false);
+    audio.set_playing(true);
+    audio.fill(&mut out, 4000);
+    assert_eq!(out, vec![1.0, 1.0, -1.0, -1.0, -1.0]);
+}
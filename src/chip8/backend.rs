@@ -0,0 +1,98 @@
+use crate::chip8::AudioStream;
+
+/// RGB colors indexed by pixel value: 0 background, 1 plane 1, 2 plane 2, 3 both planes
+pub type Palette = [(u8, u8, u8); 4];
+
+/// Black on white for plane 1, grey shades for the XO-CHIP plane 2 and plane overlap
+pub const DEFAULT_PALETTE: Palette = [(255, 255, 255), (0, 0, 0), (0x99, 0x99, 0x99), (0x55, 0x55, 0x55)];
+
+/// User actions a frontend forwards to the emulator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// save the machine state to slot n (1..=SAVE_SLOTS)
+    SaveState(usize),
+    /// restore the machine state from slot n (1..=SAVE_SLOTS)
+    LoadState(usize),
+    /// go back one frame, sent every frame while the user holds the rewind key
+    Rewind,
+    /// stop at the next instruction and hand control to the debugger, see `Chip8::break_reason()`
+    Break,
+}
+
+/// Platform services required by the emulator core.
+///
+/// A `Chip8` owns its backend, either as a concrete type or as a `Box<dyn Backend>`.
+pub trait Backend {
+    /// Draw the display, one `Vec<u8>` of pixels per row.
+    /// Each pixel is an XO-CHIP plane bitmask (0-3) indexing `palette`.
+    /// The resolution is either 64x32 or 128x64 and can change between two calls.
+    fn render(&mut self, pixels: &[Vec<u8>], palette: &Palette);
+
+    /// Start (`on == true`) or stop the buzzer.
+    /// Only called when the sound state changes.
+    fn set_sound(&mut self, on: bool);
+
+    /// Pull audio samples from the XO-CHIP audio stream, called once per 60Hz frame.
+    /// Backends without audio output can ignore it.
+    fn queue_audio(&mut self, _audio: &mut AudioStream) {}
+
+    /// Update the keypad state, `keypad[k]` is true while key `k` is pressed
+    fn poll_input(&mut self, keypad: &mut [bool]);
+
+    /// Returns true once the user asked to quit the emulator
+    fn quit_requested(&mut self) -> bool;
+
+    /// Next pending user command, polled once per frame until None
+    fn poll_command(&mut self) -> Option<Command> {
+        None
+    }
+
+    /// Outcome of a command returned by `poll_command()`
+    fn command_done(&mut self, _command: Command, _result: Result<(), String>) {}
+}
+
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn render(&mut self, pixels: &[Vec<u8>], palette: &Palette) {
+        (**self).render(pixels, palette)
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        (**self).set_sound(on)
+    }
+
+    fn queue_audio(&mut self, audio: &mut AudioStream) {
+        (**self).queue_audio(audio)
+    }
+
+    fn poll_input(&mut self, keypad: &mut [bool]) {
+        (**self).poll_input(keypad)
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        (**self).quit_requested()
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        (**self).poll_command()
+    }
+
+    fn command_done(&mut self, command: Command, result: Result<(), String>) {
+        (**self).command_done(command, result)
+    }
+}
+
+/// Backend without any output nor input, used for tests and headless runs
+#[derive(Default)]
+pub struct NullBackend;
+
+impl Backend for NullBackend {
+    fn render(&mut self, _pixels: &[Vec<u8>], _palette: &Palette) {}
+
+    fn set_sound(&mut self, _on: bool) {}
+
+    fn poll_input(&mut self, _keypad: &mut [bool]) {}
+
+    fn quit_requested(&mut self) -> bool {
+        false
+    }
+}
@@ -1,75 +1,74 @@
-use crate::chip8::Chip8;
+use crate::chip8::{Access, Backend, Breakpoint, BreakReason, Chip8, Command, DISPLAY_HEIGHT, DISPLAY_WIDTH, EmulatorError, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+use crate::chip8::{INSTR_PER_FRAME, KeyScript, Movie, NullBackend, Palette, Quirks, QuirksPreset, Register, RngKind, SAVE_SLOTS, StateError, Timing, Tracer, VIP_CPU_CYCLES_PER_FRAME, WatchKind};
 
 #[test]
 fn chip8_jmp_addr() {
     let mut chip8 = Chip8::new();
-    chip8.exec_instr(0x1555);
+    chip8.exec_instr(0x1555).unwrap();
     assert_eq!(chip8.regs.pc, 0x555);
 }
 
 #[test]
 fn chip8_call_ret() {
     let mut chip8 = Chip8::new();
-    chip8.exec_instr(0x2555);
+    chip8.exec_instr(0x2555).unwrap();
     assert_eq!(chip8.regs.pc, 0x555);
     assert_eq!(chip8.regs.sp, 1);
     assert_eq!(chip8.regs.stack[0], 0x202);
-    chip8.exec_instr(0x2777);
+    chip8.exec_instr(0x2777).unwrap();
     assert_eq!(chip8.regs.pc, 0x777);
     assert_eq!(chip8.regs.sp, 2);
     assert_eq!(chip8.regs.stack[1], 0x557);
-    chip8.exec_instr(0x00EE);
+    chip8.exec_instr(0x00EE).unwrap();
     assert_eq!(chip8.regs.pc, 0x557);
     assert_eq!(chip8.regs.sp, 1);
-    chip8.exec_instr(0x00EE);
+    chip8.exec_instr(0x00EE).unwrap();
     assert_eq!(chip8.regs.pc, 0x202);
     assert_eq!(chip8.regs.sp, 0);
 }
 
 #[test]
-#[should_panic]
 fn chip8_call_stack_overflow() {
     let mut chip8 = Chip8::new();
     chip8.regs.sp = 15;
-    chip8.exec_instr(0x2555);
+    assert_eq!(chip8.exec_instr(0x2555), Err(EmulatorError::StackOverflow { pc: 0x200, instr: 0x2555 }));
 }
 
 #[test]
-#[should_panic]
 fn chip8_ret_stack_overflow() {
     let mut chip8 = Chip8::new();
     chip8.regs.sp = 0;
-    chip8.exec_instr(0x00EE);
+    assert_eq!(chip8.exec_instr(0x00EE), Err(EmulatorError::StackUnderflow { pc: 0x200, instr: 0x00EE }));
 }
 
 #[test]
 fn chip8_skip_instr() {
     let mut chip8 = Chip8::new();
     assert_eq!(chip8.regs.pc, 0x200);
-    chip8.exec_instr(0x3455);
+    chip8.exec_instr(0x3455).unwrap();
     assert_eq!(chip8.regs.pc, 0x202);
     chip8.regs.v[4] = 0x55;
-    chip8.exec_instr(0x3455);
+    chip8.exec_instr(0x3455).unwrap();
     assert_eq!(chip8.regs.pc, 0x206);
-    chip8.exec_instr(0x4400);
+    chip8.exec_instr(0x4400).unwrap();
     assert_eq!(chip8.regs.pc, 0x20A);
-    chip8.exec_instr(0x4455);
+    chip8.exec_instr(0x4455).unwrap();
     assert_eq!(chip8.regs.pc, 0x20C);
-    chip8.exec_instr(0x5450);
+    chip8.exec_instr(0x5450).unwrap();
     assert_eq!(chip8.regs.pc, 0x20E);
     chip8.regs.v[5] = 0x55;
-    chip8.exec_instr(0x5450);
+    chip8.exec_instr(0x5450).unwrap();
     assert_eq!(chip8.regs.pc, 0x212);
 }
 
 #[test]
 fn chip8_add_byte() {
     let mut chip8 = Chip8::new();
-    chip8.exec_instr(0x70FF);
+    chip8.exec_instr(0x70FF).unwrap();
     assert_eq!(chip8.regs.v[0], 0xFF);
-    chip8.exec_instr(0x7020);
+    chip8.exec_instr(0x7020).unwrap();
     assert_eq!(chip8.regs.v[0], 0x1F);
-    chip8.exec_instr(0x7A25);
+    chip8.exec_instr(0x7A25).unwrap();
     assert_eq!(chip8.regs.v[10], 0x25);
 }
 
@@ -77,9 +76,9 @@ fn chip8_add_byte() {
 fn chip8_load_instr() {
     let mut chip8 = Chip8::new();
     assert_eq!(chip8.regs.v[4], 0x00);
-    chip8.exec_instr(0x6455);
+    chip8.exec_instr(0x6455).unwrap();
     assert_eq!(chip8.regs.v[4], 0x55);
-    chip8.exec_instr(0x8540);
+    chip8.exec_instr(0x8540).unwrap();
     assert_eq!(chip8.regs.v[5], 0x55);
 }
 
@@ -88,37 +87,37 @@ fn chip8_arithmetic_logic() {
     let mut chip8 = Chip8::new();
     chip8.regs.v[1] = 0b0011_1010;
     chip8.regs.v[2] = 0b0111_1111;
-    chip8.exec_instr(0x8121); // OR
+    chip8.exec_instr(0x8121).unwrap(); // OR
     assert_eq!(chip8.regs.v[1], 0b0111_1111);
     assert_eq!(chip8.regs.v[2], 0b0111_1111);
 
     chip8.regs.v[1] = 0b0011_1010;
-    chip8.exec_instr(0x8122); // AND
+    chip8.exec_instr(0x8122).unwrap(); // AND
     assert_eq!(chip8.regs.v[1], 0b0011_1010);
 
     chip8.regs.v[1] = 0b0011_1010;
-    chip8.exec_instr(0x8123); // XOR
+    chip8.exec_instr(0x8123).unwrap(); // XOR
     assert_eq!(chip8.regs.v[1], 0b0100_0101);
 
     chip8.regs.v[1] = 0x20;
     chip8.regs.v[2] = 0x10;
-    chip8.exec_instr(0x8124); // ADD
+    chip8.exec_instr(0x8124).unwrap(); // ADD
     assert_eq!(chip8.regs.v[1], 0x30);
     assert_eq!(chip8.regs.v[0xF], 0);
 
     chip8.regs.v[1] = 0xFF;
     chip8.regs.v[2] = 0x10;
-    chip8.exec_instr(0x8124); // ADD
+    chip8.exec_instr(0x8124).unwrap(); // ADD
     assert_eq!(chip8.regs.v[1], 0x0F);
     assert_eq!(chip8.regs.v[0xF], 1);
 
     chip8.regs.v[1] = 0x80;
-    chip8.exec_instr(0x812E); // SHL
+    chip8.exec_instr(0x812E).unwrap(); // SHL
     assert_eq!(chip8.regs.v[1], 0x0);
     assert_eq!(chip8.regs.v[0xF], 1);
 
     chip8.regs.v[1] = 10;
-    chip8.exec_instr(0x812E); // SHL
+    chip8.exec_instr(0x812E).unwrap(); // SHL
     assert_eq!(chip8.regs.v[1], 20);
     assert_eq!(chip8.regs.v[0xF], 0);
 }
@@ -129,8 +128,787 @@ fn chip8_bcd() {
     chip8.regs.i = 0x400;
     chip8.regs.v[3] = 197;
 
-    chip8.exec_instr(0xF333);
+    chip8.exec_instr(0xF333).unwrap();
     assert_eq!(chip8.memory[0x400], 1);
     assert_eq!(chip8.memory[0x401], 9);
     assert_eq!(chip8.memory[0x402], 7);
 }
+
+#[test]
+fn chip8_invalid_key_and_digit() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.v[2] = 0x10;
+    assert_eq!(chip8.exec_instr(0xE29E), Err(EmulatorError::InvalidKey { pc: 0x200, instr: 0xE29E, key: 0x10 }));
+    chip8.regs.pc = 0x200;
+    assert_eq!(chip8.exec_instr(0xF229), Err(EmulatorError::InvalidDigit { pc: 0x200, instr: 0xF229, digit: 0x10 }));
+}
+
+#[test]
+fn chip8_unknown_opcode() {
+    let mut chip8 = Chip8::new();
+    assert_eq!(chip8.exec_instr(0x5121), Err(EmulatorError::UnknownOpcode { pc: 0x200, instr: 0x5121 }));
+}
+
+#[test]
+fn chip8_memory_out_of_bounds() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.i = 0xFFFE;
+    assert_eq!(chip8.exec_instr(0xF333), Err(EmulatorError::MemoryOutOfBounds { pc: 0x200, instr: 0xF333, addr: 0x10000 }));
+    chip8.regs.i = usize::MAX;
+    assert_eq!(chip8.exec_instr(0xF255), Err(EmulatorError::MemoryOutOfBounds { pc: 0x202, instr: 0xF255, addr: usize::MAX }));
+}
+
+#[test]
+fn chip8_step_error_keeps_pc() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(vec![0x00, 0xEE]).unwrap();
+    assert_eq!(chip8.step(), Err(EmulatorError::StackUnderflow { pc: 0x200, instr: 0x00EE }));
+    assert_eq!(chip8.regs.pc, 0x200);
+}
+
+#[test]
+fn chip8_run_cycles() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; JP 0x200
+    chip8.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+    chip8.run_cycles(6).unwrap();
+    assert_eq!(chip8.regs.v[0], 3);
+    assert_eq!(chip8.regs.pc, 0x200);
+}
+
+#[test]
+fn chip8_run_frame_ticks_timers() {
+    let mut chip8 = Chip8::new();
+    // JP 0x200
+    chip8.load_rom(vec![0x12, 0x00]).unwrap();
+    chip8.regs.dt = 2;
+    chip8.regs.st = 1;
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.dt, 1);
+    assert_eq!(chip8.regs.st, 0);
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.dt, 0);
+    assert_eq!(chip8.regs.st, 0);
+    assert_eq!(chip8.regs.pc, 0x200);
+}
+
+#[test]
+fn chip8_run_frame_stops_on_error() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; RET
+    chip8.load_rom(vec![0x70, 0x01, 0x00, 0xEE]).unwrap();
+    chip8.regs.dt = 5;
+    assert_eq!(chip8.run_frame(), Err(EmulatorError::StackUnderflow { pc: 0x202, instr: 0x00EE }));
+    assert_eq!(chip8.regs.v[0], 1);
+    assert_eq!(chip8.regs.dt, 5);
+}
+
+#[derive(Default)]
+struct RecordingBackend {
+    frames: usize,
+    sound: Vec<bool>,
+}
+
+impl Backend for RecordingBackend {
+    fn render(&mut self, pixels: &[Vec<u8>], _palette: &Palette) {
+        assert_eq!(pixels.len(), DISPLAY_HEIGHT);
+        self.frames += 1;
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        self.sound.push(on);
+    }
+
+    fn poll_input(&mut self, keypad: &mut [bool]) {
+        keypad[3] = true;
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.frames >= 2
+    }
+}
+
+#[test]
+fn chip8_run_with_backend() {
+    let mut chip8 = Chip8::new_with_backend(RecordingBackend::default());
+    // JP 0x200
+    chip8.load_rom(vec![0x12, 0x00]).unwrap();
+    chip8.regs.dt = 10;
+    chip8.regs.st = 2;
+    chip8.run().unwrap();
+    assert_eq!(chip8.backend().frames, 3);
+    // sound timer expires after the second frame, delay timer is silent
+    assert_eq!(chip8.backend().sound, vec![true, false]);
+    assert!(chip8.keypad[3]);
+    assert!(chip8.quit_requested());
+}
+
+#[test]
+fn chip8_run_silences_buzzer_on_error() {
+    let mut chip8 = Chip8::new_with_backend(RecordingBackend::default());
+    // ADD V0, 1; SE V0, 4; JP 0x200; RET, in the second frame
+    chip8.load_rom(vec![0x70, 0x01, 0x30, 0x04, 0x12, 0x00, 0x00, 0xEE]).unwrap();
+    chip8.regs.st = 10;
+    assert_eq!(chip8.run(), Err(EmulatorError::StackUnderflow { pc: 0x206, instr: 0x00EE }));
+    assert_eq!(chip8.backend().sound, vec![true, false]);
+    assert!(!chip8.quit_requested());
+}
+
+#[test]
+fn chip8_quirks_shift() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.v[1] = 0x01;
+    chip8.regs.v[2] = 0x82;
+    chip8.exec_instr(0x8126).unwrap(); // SHR
+    assert_eq!(chip8.regs.v[1], 0x00);
+    assert_eq!(chip8.regs.v[0xF], 1);
+
+    chip8.set_quirks(Quirks { shift_uses_vy: true, ..Quirks::default() });
+    chip8.exec_instr(0x8126).unwrap(); // SHR
+    assert_eq!(chip8.regs.v[1], 0x41);
+    assert_eq!(chip8.regs.v[0xF], 0);
+    chip8.exec_instr(0x812E).unwrap(); // SHL
+    assert_eq!(chip8.regs.v[1], 0x04);
+    assert_eq!(chip8.regs.v[0xF], 1);
+}
+
+#[test]
+fn chip8_quirks_load_store_increment() {
+    for (preset, i) in [(QuirksPreset::SuperChip11, 0x400), (QuirksPreset::Chip48, 0x402), (QuirksPreset::CosmacVip, 0x403)].iter() {
+        let mut chip8 = Chip8::new_with_quirks(NullBackend, Quirks::from(*preset));
+        chip8.regs.i = 0x400;
+        chip8.exec_instr(0xF255).unwrap();
+        assert_eq!(chip8.regs.i, *i);
+        chip8.regs.i = 0x400;
+        chip8.exec_instr(0xF265).unwrap();
+        assert_eq!(chip8.regs.i, *i);
+    }
+}
+
+#[test]
+fn chip8_quirks_jump() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.v[0] = 0x10;
+    chip8.regs.v[3] = 0x20;
+    chip8.exec_instr(0xB300).unwrap();
+    assert_eq!(chip8.regs.pc, 0x310);
+
+    chip8.set_quirks(Quirks::from(QuirksPreset::SuperChip11));
+    chip8.exec_instr(0xB300).unwrap();
+    assert_eq!(chip8.regs.pc, 0x320);
+}
+
+#[test]
+fn chip8_quirks_vf_reset() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.v[0xF] = 1;
+    chip8.exec_instr(0x8121).unwrap(); // OR
+    assert_eq!(chip8.regs.v[0xF], 1);
+
+    chip8.set_quirks(Quirks { vf_reset: true, ..Quirks::default() });
+    chip8.exec_instr(0x8122).unwrap(); // AND
+    assert_eq!(chip8.regs.v[0xF], 0);
+}
+
+#[test]
+fn chip8_quirks_display_wait() {
+    let mut chip8 = Chip8::new_with_quirks(NullBackend, Quirks { display_wait: true, ..Quirks::default() });
+    // DRW V0, V0, 1; DRW V0, V0, 1
+    chip8.load_rom(vec![0xD0, 0x01, 0xD0, 0x01]).unwrap();
+    chip8.run_cycles(3).unwrap();
+    assert_eq!(chip8.regs.pc, 0x200);
+    chip8.tick_timers();
+    chip8.run_cycles(3).unwrap();
+    assert_eq!(chip8.regs.pc, 0x202);
+    chip8.tick_timers();
+    chip8.run_cycles(1).unwrap();
+    assert_eq!(chip8.regs.pc, 0x204);
+}
+
+#[test]
+fn chip8_schip_hires_and_large_sprite() {
+    let mut chip8 = Chip8::new();
+    chip8.exec_instr(0x00FF).unwrap();
+    assert_eq!(chip8.display_size(), (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT));
+
+    chip8.regs.i = 0x400;
+    for byte in &mut chip8.memory[0x400..0x420] {
+        *byte = 0xFF;
+    }
+    chip8.regs.v[0] = 120;
+    chip8.regs.v[1] = 60;
+    chip8.set_quirks(Quirks::from(QuirksPreset::SuperChip11));
+    chip8.exec_instr(0xD010).unwrap();
+    assert_eq!(chip8.regs.v[0xF], 0);
+    assert_eq!(chip8.display.pixels()[63][127], 1);
+    assert_eq!(chip8.display.pixels()[0][0], 0);
+
+    chip8.exec_instr(0x00FE).unwrap();
+    assert_eq!(chip8.display_size(), (DISPLAY_WIDTH, DISPLAY_HEIGHT));
+}
+
+#[test]
+fn chip8_schip_big_font_and_rpl_flags() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.v[2] = 3;
+    chip8.exec_instr(0xF230).unwrap();
+    assert_eq!(chip8.regs.i, 80 + 30);
+    assert_eq!(chip8.memory[chip8.regs.i], 0x3C);
+
+    chip8.regs.v[0] = 0xAA;
+    chip8.regs.v[1] = 0xBB;
+    chip8.exec_instr(0xF175).unwrap();
+    chip8.regs.v[0] = 0;
+    chip8.regs.v[1] = 0;
+    chip8.exec_instr(0xF085).unwrap();
+    assert_eq!(chip8.regs.v[0], 0xAA);
+    assert_eq!(chip8.regs.v[1], 0);
+}
+
+#[test]
+fn chip8_schip_exit() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; EXIT
+    chip8.load_rom(vec![0x70, 0x01, 0x00, 0xFD]).unwrap();
+    chip8.run_frame().unwrap();
+    assert!(chip8.exited());
+    assert_eq!(chip8.regs.v[0], 1);
+    assert_eq!(chip8.regs.pc, 0x202);
+}
+
+#[test]
+fn chip8_xochip_long_load_and_skip() {
+    let mut chip8 = Chip8::new();
+    // LD I, 0x1234; SE V0, 0; LD I, 0xABCD; ADD V1, 1
+    chip8.load_rom(vec![0xF0, 0x00, 0x12, 0x34, 0x30, 0x00, 0xF0, 0x00, 0xAB, 0xCD, 0x71, 0x01]).unwrap();
+    chip8.step().unwrap();
+    assert_eq!(chip8.regs.i, 0x1234);
+    assert_eq!(chip8.regs.pc, 0x204);
+    chip8.step().unwrap();
+    assert_eq!(chip8.regs.pc, 0x20A);
+    chip8.step().unwrap();
+    assert_eq!(chip8.regs.v[1], 1);
+    assert_eq!(chip8.regs.i, 0x1234);
+}
+
+#[test]
+fn chip8_xochip_register_range() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.i = 0xF000;
+    chip8.regs.v[2] = 2;
+    chip8.regs.v[3] = 3;
+    chip8.regs.v[4] = 4;
+    chip8.exec_instr(0x5242).unwrap();
+    assert_eq!(chip8.memory[0xF000..0xF003], [2, 3, 4]);
+    assert_eq!(chip8.regs.i, 0xF000);
+
+    chip8.exec_instr(0x5A83).unwrap(); // reversed: VA = 2, V9 = 3, V8 = 4
+    assert_eq!(chip8.regs.v[8..=0xA], [4, 3, 2]);
+}
+
+#[test]
+fn chip8_xochip_planes() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.i = 0x400;
+    chip8.memory[0x400] = 0x80;
+    chip8.memory[0x401] = 0xC0;
+    chip8.exec_instr(0xF301).unwrap(); // PLANE 3
+    chip8.exec_instr(0xD011).unwrap();
+    assert_eq!(chip8.display.pixels()[0][0], 3);
+    assert_eq!(chip8.display.pixels()[0][1], 2);
+    assert_eq!(chip8.regs.v[0xF], 0);
+
+    chip8.exec_instr(0xF201).unwrap(); // PLANE 2
+    chip8.exec_instr(0x00D1).unwrap(); // SCROLL-UP 1
+    chip8.exec_instr(0x00E0).unwrap();
+    assert_eq!(chip8.display.pixels()[0][0], 1);
+
+    assert_eq!(chip8.exec_instr(0xF401), Err(EmulatorError::UnknownOpcode { pc: 0x20A, instr: 0xF401 }));
+}
+
+#[test]
+fn chip8_xochip_audio() {
+    let mut chip8 = Chip8::new();
+    chip8.regs.i = 0x400;
+    chip8.memory[0x400] = 0xAA;
+    chip8.exec_instr(0xF002).unwrap();
+    assert!(chip8.audio().pattern_loaded());
+    assert_eq!(chip8.audio().pattern()[0], 0xAA);
+
+    chip8.regs.v[5] = 112;
+    chip8.exec_instr(0xF53A).unwrap();
+    assert_eq!(chip8.audio().pitch(), 112);
+
+    chip8.regs.st = 2;
+    chip8.tick_timers();
+    assert!(chip8.audio().is_playing());
+    chip8.tick_timers();
+    assert!(!chip8.audio().is_playing());
+}
+
+#[test]
+fn chip8_delay_timer_is_silent() {
+    let mut chip8 = Chip8::new_with_backend(RecordingBackend::default());
+    // JP 0x200
+    chip8.load_rom(vec![0x12, 0x00]).unwrap();
+    chip8.regs.dt = 10;
+    chip8.run().unwrap();
+    assert!(chip8.backend().sound.is_empty());
+}
+
+#[test]
+fn chip8_save_load_state() {
+    let mut chip8 = Chip8::new_with_quirks(NullBackend, Quirks::from(QuirksPreset::XoChip));
+    // LD V0, 0x05; CALL 0x300
+    chip8.load_rom(vec![0x60, 0x05, 0x23, 0x00]).unwrap();
+    chip8.run_cycles(2).unwrap();
+    chip8.exec_instr(0x00FF).unwrap();
+    chip8.exec_instr(0xF029).unwrap();
+    chip8.exec_instr(0xD015).unwrap();
+    chip8.regs.dt = 7;
+    chip8.keypad[4] = false;
+    let state = chip8.save_state();
+
+    let mut restored = Chip8::new();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.regs.v, chip8.regs.v);
+    assert_eq!(restored.regs.pc, 0x306);
+    assert_eq!(restored.regs.sp, 1);
+    assert_eq!(restored.regs.stack[0], 0x204);
+    assert_eq!(restored.regs.i, 25);
+    assert_eq!(restored.regs.dt, 7);
+    assert_eq!(restored.display.pixels(), chip8.display.pixels());
+    assert_eq!(restored.display_size(), (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT));
+    assert_eq!(restored.keypad, chip8.keypad);
+    assert_eq!(restored.quirks(), chip8.quirks());
+    assert_eq!(restored.memory, chip8.memory);
+    assert_eq!(restored.save_state(), state);
+}
+
+#[test]
+fn chip8_load_state_rejects_corrupted_data() {
+    let mut chip8 = Chip8::new();
+    let mut state = chip8.save_state();
+
+    match chip8.load_state(&state[..state.len() - 1]) {
+        Err(StateError::Truncated) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let len = state.len();
+    state[len / 2] ^= 0xFF;
+    match chip8.load_state(&state) {
+        Err(StateError::ChecksumMismatch { .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+
+    state[0] = b'X';
+    match chip8.load_state(&state) {
+        Err(StateError::BadMagic) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn chip8_save_slots() {
+    let mut chip8 = Chip8::new();
+    assert!(chip8.save_slot(1).is_err());
+
+    let rom_path = std::env::temp_dir().join(format!("chip8_save_slots_{}.ch8", std::process::id()));
+    chip8.set_save_path(&rom_path);
+    assert!(chip8.save_slot(0).is_err());
+    assert!(chip8.save_slot(SAVE_SLOTS + 1).is_err());
+
+    chip8.regs.v[1] = 0x42;
+    chip8.save_slot(3).unwrap();
+    chip8.regs.v[1] = 0;
+    chip8.load_slot(3).unwrap();
+    assert_eq!(chip8.regs.v[1], 0x42);
+
+    std::fs::remove_file(format!("{}.state3", rom_path.display())).unwrap();
+}
+
+#[test]
+fn chip8_rewind() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; JP 0x200
+    chip8.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+    assert!(!chip8.rewind().unwrap());
+
+    chip8.set_rewind_depth(1);
+    for _ in 0..100 {
+        chip8.run_frame().unwrap();
+    }
+    let v0 = chip8.regs.v[0];
+    assert_eq!(chip8.rewind_len(), 60);
+    assert!(chip8.rewind_size() < 2 * chip8.save_state().len());
+
+    assert!(chip8.rewind().unwrap());
+    assert_eq!(chip8.regs.v[0], v0.wrapping_sub((INSTR_PER_FRAME / 2) as u8));
+    for _ in 0..59 {
+        assert!(chip8.rewind().unwrap());
+    }
+    assert_eq!(chip8.regs.v[0], v0.wrapping_sub((60 * INSTR_PER_FRAME / 2) as u8));
+    assert!(!chip8.rewind().unwrap());
+
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.v[0], v0.wrapping_sub((59 * INSTR_PER_FRAME / 2) as u8));
+}
+
+#[test]
+fn chip8_breakpoints() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; ADD V1, 1; JP 0x200
+    chip8.load_rom(vec![0x70, 0x01, 0x71, 0x01, 0x12, 0x00]).unwrap();
+    chip8.regs.dt = 10;
+    chip8.debugger_mut().add_breakpoint(0x202);
+
+    // the frame stops before the breakpoint, timers are not ticked
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Breakpoint(0x202)));
+    assert_eq!((chip8.regs.pc, chip8.regs.v[0], chip8.regs.v[1], chip8.regs.dt), (0x202, 1, 0, 10));
+
+    // stays on the breakpoint until resumed
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.pc, 0x202);
+    chip8.resume();
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Breakpoint(0x202)));
+    assert_eq!((chip8.regs.v[0], chip8.regs.v[1]), (2, 1));
+
+    // step ignores breakpoints
+    chip8.step().unwrap();
+    assert_eq!((chip8.regs.pc, chip8.regs.v[1]), (0x204, 2));
+
+    assert!(chip8.debugger_mut().remove_breakpoint(0x202));
+    chip8.resume();
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.break_reason(), None);
+    assert_eq!(chip8.regs.dt, 9);
+}
+
+struct BreakingBackend {
+    frames: u32,
+    sent: bool,
+}
+
+impl Backend for BreakingBackend {
+    fn render(&mut self, _pixels: &[Vec<u8>], _palette: &Palette) {
+        self.frames += 1;
+    }
+
+    fn set_sound(&mut self, _on: bool) {}
+
+    fn poll_input(&mut self, _keypad: &mut [bool]) {}
+
+    fn quit_requested(&mut self) -> bool {
+        false
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        if self.frames == 1 && !self.sent {
+            self.sent = true;
+            return Some(Command::Break);
+        }
+        None
+    }
+}
+
+#[test]
+fn chip8_run_stops_on_break_command() {
+    let mut chip8 = Chip8::new_with_backend(BreakingBackend { frames: 0, sent: false });
+    // JP 0x200
+    chip8.load_rom(vec![0x12, 0x00]).unwrap();
+    chip8.run().unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Interrupted));
+    assert_eq!(chip8.backend().frames, 2);
+}
+
+#[test]
+fn chip8_watchpoints() {
+    let mut chip8 = Chip8::new();
+    // LD I, 0x300; LD B, V0; LD V1, [I]; DRW V0, V0, 2; LD [I], V1; JP 0x20A
+    chip8.load_rom(vec![0xA3, 0x00, 0xF0, 0x33, 0xF1, 0x65, 0xD0, 0x02, 0xF1, 0x55, 0x12, 0x0A]).unwrap();
+    chip8.regs.v[0] = 123;
+    chip8.debugger_mut().add_watchpoint(0x301, 2, WatchKind::Write);
+
+    // Fx33 writes the ones digit first, the break happens after the instruction
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Watchpoint { access: Access::Write, addr: 0x302, value: 3 }));
+    assert_eq!(chip8.regs.pc, 0x204);
+
+    // reads by Fx65 and Dxyn sprite fetches
+    chip8.debugger_mut().add_watchpoint(0x301, 1, WatchKind::Read);
+    chip8.resume();
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Watchpoint { access: Access::Read, addr: 0x301, value: 2 }));
+    assert_eq!(chip8.regs.pc, 0x206);
+    chip8.resume();
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Watchpoint { access: Access::Read, addr: 0x301, value: 2 }));
+    assert_eq!(chip8.regs.pc, 0x208);
+
+    // writes by Fx55
+    chip8.debugger_mut().add_watchpoint(0x301, 1, WatchKind::ReadWrite);
+    chip8.resume();
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Watchpoint { access: Access::Write, addr: 0x301, value: 2 }));
+    assert_eq!(chip8.regs.pc, 0x20A);
+
+    assert!(chip8.debugger_mut().remove_watchpoint(0x301));
+    chip8.resume();
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), None);
+
+    // a length reaching past the end of the address space watches up to the end
+    chip8.debugger_mut().add_watchpoint(0x302, usize::MAX, WatchKind::Write);
+    chip8.regs.pc = 0x202;
+    chip8.regs.v[0] = 123;
+    chip8.run_cycles(10).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Watchpoint { access: Access::Write, addr: 0x302, value: 3 }));
+}
+
+#[test]
+fn chip8_register_breakpoints() {
+    let mut chip8 = Chip8::new();
+    // ADD V3, 1; ADD I, V3; JP 0x200
+    chip8.load_rom(vec![0x73, 0x01, 0xF3, 0x1E, 0x12, 0x00]).unwrap();
+    chip8.debugger_mut().add_register_breakpoint(Register::V(3), 2);
+    chip8.debugger_mut().add_register_breakpoint(Register::I, 1);
+
+    chip8.run_cycles(100).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Register { reg: Register::I, value: 1 }));
+    assert_eq!(chip8.regs.pc, 0x204);
+
+    chip8.resume();
+    chip8.run_cycles(100).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Register { reg: Register::V(3), value: 2 }));
+    assert_eq!(chip8.regs.pc, 0x202);
+
+    // only breaks when the value changes
+    chip8.resume();
+    chip8.run_cycles(100).unwrap();
+    assert_eq!(chip8.break_reason(), None);
+    assert_eq!("vA".parse::<Register>(), Ok(Register::V(10)));
+}
+
+#[test]
+fn chip8_conditional_breakpoints() {
+    let mut chip8 = Chip8::new();
+    // ADD V3, 1; LD [I], V3; JP 0x200
+    chip8.load_rom(vec![0x73, 0x01, 0xF3, 0x55, 0x12, 0x00]).unwrap();
+    chip8.regs.i = 0x300;
+    let condition = "v3 == 0x10 && mem[0x303] != 0".parse().unwrap();
+    chip8.debugger_mut().set_breakpoint(0x202, Breakpoint::new(Some(condition), 0));
+
+    chip8.run_cycles(1000).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Breakpoint(0x202)));
+    assert_eq!((chip8.regs.v[3], chip8.memory[0x303]), (0x10, 0x0F));
+
+    // break on the 5th hit
+    chip8.debugger_mut().set_breakpoint(0x200, Breakpoint::new(None, 5));
+    chip8.debugger_mut().remove_breakpoint(0x202);
+    chip8.resume();
+    chip8.run_cycles(1000).unwrap();
+    assert_eq!(chip8.break_reason(), Some(BreakReason::Breakpoint(0x200)));
+    assert_eq!(chip8.regs.v[3], 0x14);
+    assert_eq!(chip8.debugger().breakpoints().next().unwrap().1.hits(), 5);
+}
+
+/// Trace output shared with the test
+#[derive(Clone, Default)]
+struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(String::from).collect()
+    }
+}
+
+#[test]
+fn chip8_trace() {
+    // 200: LD V0, #01  202: ADD V0, #01  204: CALL #208  206: unknown  208: RET
+    let rom = vec![0x60, 0x01, 0x70, 0x01, 0x22, 0x08, 0xFF, 0xFF, 0x00, 0xEE];
+
+    let output = SharedBuffer::default();
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(rom.clone()).unwrap();
+    chip8.set_tracer(Some(Tracer::new(Box::new(output.clone()))));
+    chip8.run_cycles(2).unwrap();
+    let lines = output.lines();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("0000000001 PC=200 OP=6001     LD V0, #01               V=00 00"));
+    assert!(lines[1].starts_with("0000000002 PC=202 OP=7001     ADD V0, #01              V=01 00"));
+
+    // only the last 2 instructions in 204-2FF, dumped on error
+    let output = SharedBuffer::default();
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(rom).unwrap();
+    let mut tracer = Tracer::new(Box::new(output.clone()));
+    tracer.add_range(0x204, 0x2FF);
+    tracer.set_ring_buffer(2);
+    chip8.set_tracer(Some(tracer));
+    chip8.run_cycles(4).unwrap();
+    assert!(output.lines().is_empty());
+
+    assert!(chip8.run_cycles(1).is_err());
+    let lines = output.lines();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("0000000004 PC=208 OP=00EE     RET"));
+    assert!(lines[1].starts_with("0000000005 PC=206 OP=FFFF     DW #FFFF"));
+    assert_eq!(lines[2], "error: Unknown instruction FFFF at 206");
+    assert_eq!(chip8.tracer().unwrap().cycle(), 5);
+}
+
+#[test]
+fn chip8_seeded_rnd() {
+    let random_bytes = |seed| {
+        let mut chip8 = Chip8::new();
+        chip8.seed_rng(seed);
+        (0..16).map(|x| {
+            chip8.exec_instr(0xC0FF | (x << 8)).unwrap();
+            chip8.regs.v[x as usize]
+        }).collect::<Vec<u8>>()
+    };
+    assert_eq!(random_bytes(42), random_bytes(42));
+    assert_ne!(random_bytes(42), random_bytes(43));
+}
+
+#[test]
+fn chip8_save_state_restores_rng() {
+    let mut chip8 = Chip8::new();
+    chip8.set_rng(RngKind::Table.create(Some(0x1234)));
+    chip8.exec_instr(0xC0FF).unwrap();
+    let state = chip8.save_state();
+    chip8.exec_instr(0xC1FF).unwrap();
+
+    let mut restored = Chip8::new();
+    restored.set_rng(RngKind::Table.create(None));
+    restored.load_state(&state).unwrap();
+    restored.exec_instr(0xC1FF).unwrap();
+    assert_eq!(restored.regs.v[1], chip8.regs.v[1]);
+
+    // the default xorshift generator cannot take a table generator state
+    let mut other = Chip8::new();
+    match other.load_state(&state) {
+        Err(StateError::Invalid(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn chip8_movie_record_and_replay() {
+    let rom = include_bytes!("../../roms/PONG.ch8").to_vec();
+    let mut recorder = Chip8::new();
+    recorder.load_rom(rom.clone()).unwrap();
+    recorder.start_recording(&rom, 7, RngKind::XorShift, 10);
+    recorder.run_headless(100, &"5=1 20= 40=4 70=C".parse().unwrap()).unwrap();
+    let movie = recorder.stop_movie().unwrap();
+    assert_eq!(movie.len(), 100);
+    assert_eq!(movie.keys(30), Some(0));
+    assert_eq!(movie.keys(50), Some(1 << 4));
+    assert!(movie.hash(9).is_some() && movie.hash(10).is_none());
+
+    let replay = |movie: Movie| {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(rom.clone()).unwrap();
+        chip8.play_movie(movie, &rom).unwrap();
+        chip8.run_headless(100, &KeyScript::default()).unwrap();
+        assert!(chip8.movie_finished());
+        (chip8.movie_desync(), chip8.save_state())
+    };
+    let (desync, state) = replay(movie.clone());
+    assert_eq!(desync, None);
+    assert_eq!(state, recorder.save_state());
+
+    let hash = movie.hash(49).unwrap();
+    let tampered = movie.to_string().replace(&format!("hash 49 {:08x}", hash), &format!("hash 49 {:08x}", !hash));
+    let desync = replay(tampered.parse().unwrap()).0.unwrap();
+    assert_eq!((desync.frame, desync.expected, desync.actual), (49, !hash, hash));
+}
+
+#[test]
+fn chip8_cpu_frequency() {
+    let mut chip8 = Chip8::new();
+    // ADD V0, 1; JP 0x200
+    chip8.load_rom(vec![0x70, 0x01, 0x12, 0x00]).unwrap();
+    chip8.set_tracer(Some(Tracer::new(Box::new(std::io::sink()))));
+    assert_eq!(chip8.cpu_frequency(), INSTR_PER_FRAME * 60);
+
+    chip8.set_cpu_frequency(500);
+    chip8.regs.dt = 60;
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.tracer().unwrap().cycle(), 8);
+    for _ in 1..60 {
+        chip8.run_frame().unwrap();
+    }
+    // 500 instructions per second exactly, the timers still tick at 60Hz
+    assert_eq!(chip8.tracer().unwrap().cycle(), 500);
+    assert_eq!(chip8.regs.dt, 0);
+
+    chip8.set_instructions_per_frame(1000);
+    chip8.regs.dt = 1;
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.tracer().unwrap().cycle(), 1500);
+    assert_eq!(chip8.regs.dt, 0);
+
+    // the per-frame budget does not overflow at the highest frequency
+    chip8.set_instructions_per_frame(u32::MAX);
+    assert_eq!(chip8.cpu_frequency(), u32::MAX);
+    chip8.debugger_mut().set_breakpoint(0x202, Breakpoint::new(None, 0));
+    for _ in 0..2 {
+        chip8.resume();
+        chip8.run_frame().unwrap();
+    }
+}
+
+#[test]
+fn chip8_vip_timing() {
+    let mut chip8 = Chip8::new();
+    // LD V0, 1; ADD V0, 1; JP 0x202
+    chip8.load_rom(vec![0x60, 0x01, 0x70, 0x01, 0x12, 0x02]).unwrap();
+    assert_eq!(chip8.step(), Ok(1));
+    chip8.set_timing(Timing::CosmacVip);
+    assert_eq!(chip8.step(), Ok(50));
+    assert_eq!(chip8.step(), Ok(52));
+
+    // 25 more additions fit in the 2592 cycles of the frame,
+    // the 8 cycles overrunning it are taken from the next frame
+    chip8.regs.dt = 2;
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.dt, 1);
+    assert_eq!(chip8.regs.v[0], 2 + 25);
+    assert_eq!(chip8.frame_cycles, 8);
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.regs.v[0], 2 + 25 + 25);
+    assert_eq!(chip8.regs.dt, 0);
+
+    // Dxyn waits for the interrupt: one sprite per frame, whatever the quirks
+    let mut chip8 = Chip8::new();
+    // DRW V0, V0, 1; JP 0x200
+    chip8.load_rom(vec![0xD0, 0x01, 0x12, 0x00]).unwrap();
+    chip8.set_timing(Timing::CosmacVip);
+    assert!(!chip8.quirks().display_wait);
+    assert_eq!(chip8.step(), Ok(VIP_CPU_CYCLES_PER_FRAME));
+    assert_eq!(chip8.regs.pc, 0x200);
+    chip8.frame_cycles = 0;
+    chip8.run_frame().unwrap();
+    // a sprite is drawn after the interrupt of every frame, the first frame has none
+    assert_eq!(chip8.regs.v[0xF], 0);
+    assert_eq!(chip8.display.pixels()[0][0], 0);
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.display.pixels()[0][0], 1);
+    chip8.run_frame().unwrap();
+    assert_eq!((chip8.display.pixels()[0][0], chip8.regs.v[0xF]), (0, 1));
+}
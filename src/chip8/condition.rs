@@ -0,0 +1,329 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::Registers;
+
+/// Breakpoint condition evaluated against the registers and memory, e.g. `v3 == 0x10 && i > 0x300`.
+///
+/// Operators by increasing precedence: `||`, `&&`, comparisons (`== != < <= > >=`),
+/// `+ -`, `* / % & | ^`, unary `! -`.
+/// Operands: decimal or `0x` hexadecimal numbers, `v0`-`vf`, `i`, `pc`, `sp`, `dt`, `st`,
+/// `mem[addr]` and parenthesized expressions. Comparisons and logical operators yield 0 or 1,
+/// a condition holds when it evaluates to a non-zero value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn holds(&self, regs: &Registers, memory: &[u8]) -> bool {
+        self.expr.eval(regs, memory) != 0
+    }
+
+    pub fn eval(&self, regs: &Registers, memory: &[u8]) -> i64 {
+        self.expr.eval(regs, memory)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { tokens: tokenize(s)?, pos: 0 };
+        let expr = parser.or()?;
+        if let Some((token, column)) = parser.tokens.get(parser.pos) {
+            return Err(format!("Unexpected {} at column {}", token, column));
+        }
+        Ok(Condition { source: s.trim().to_string(), expr })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(i64),
+    Register(Operand),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, regs: &Registers, memory: &[u8]) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(operand) => match operand {
+                Operand::V(x) => regs.v[*x] as i64,
+                Operand::I => regs.i as i64,
+                Operand::Pc => regs.pc as i64,
+                Operand::Sp => regs.sp as i64,
+                Operand::Dt => regs.dt as i64,
+                Operand::St => regs.st as i64,
+            },
+            // out of range reads yield 0
+            Expr::Memory(addr) => {
+                let addr = addr.eval(regs, memory);
+                if addr < 0 { 0 } else { memory.get(addr as usize).map_or(0, |value| *value as i64) }
+            }
+            Expr::Not(expr) => (expr.eval(regs, memory) == 0) as i64,
+            Expr::Neg(expr) => expr.eval(regs, memory).wrapping_neg(),
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(regs, memory);
+                // short-circuit the logical operators
+                match op {
+                    BinaryOp::Or if left != 0 => return 1,
+                    BinaryOp::And if left == 0 => return 0,
+                    _ => {}
+                }
+                let right = right.eval(regs, memory);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                    BinaryOp::Eq => (left == right) as i64,
+                    BinaryOp::Ne => (left != right) as i64,
+                    BinaryOp::Lt => (left < right) as i64,
+                    BinaryOp::Le => (left <= right) as i64,
+                    BinaryOp::Gt => (left > right) as i64,
+                    BinaryOp::Ge => (left >= right) as i64,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    // division by zero yields 0
+                    BinaryOp::Div => left.checked_div(right).unwrap_or(0),
+                    BinaryOp::Rem => left.checked_rem(right).unwrap_or(0),
+                    BinaryOp::BitAnd => left & right,
+                    BinaryOp::BitOr => left | right,
+                    BinaryOp::BitXor => left ^ right,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{}", value),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+/// Longest symbols first so that `&&` is not read as two `&`
+const SYMBOLS: [&str; 20] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%",
+                             "&", "|", "^", "!", "(", ")", "["];
+
+/// Split `s` into tokens, each with its 1-based column
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        let c = rest.chars().next().unwrap();
+        let column = pos + 1;
+
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+            let word = &rest[..len];
+            let token = if c.is_ascii_digit() {
+                let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse::<i64>(),
+                };
+                Token::Number(value.map_err(|_| format!("Invalid number {} at column {}", word, column))?)
+            } else {
+                Token::Ident(word.to_ascii_lowercase())
+            };
+            tokens.push((token, column));
+            pos += len;
+        } else if c == ']' {
+            tokens.push((Token::Symbol("]"), column));
+            pos += 1;
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol))
+                .ok_or_else(|| format!("Unexpected character {} at column {}", c, column))?;
+            tokens.push((Token::Symbol(symbol), column));
+            pos += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser, one method per precedence level
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Consume the next token if it is one of `symbols`
+    fn accept(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some((Token::Symbol(symbol), _)) if symbols.contains(symbol) => {
+                self.pos += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.accept(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(self.unexpected(&format!("expected {}", symbol))),
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((token, column)) => format!("Unexpected {} at column {}, {}", token, column, expected),
+            None => format!("Unexpected end of condition, {}", expected),
+        }
+    }
+
+    /// Left-associative binary operators of one precedence level
+    fn binary(&mut self, ops: &[(&'static str, BinaryOp)],
+              operand: fn(&mut Parser) -> Result<Expr, String>) -> Result<Expr, String> {
+        let symbols: Vec<&'static str> = ops.iter().map(|(symbol, _)| *symbol).collect();
+        let mut expr = operand(self)?;
+        while let Some(symbol) = self.accept(&symbols) {
+            let op = ops.iter().find(|(s, _)| *s == symbol).unwrap().1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(operand(self)?));
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Parser::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Parser::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<=", BinaryOp::Le),
+                      (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)], Parser::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Parser::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div), ("%", BinaryOp::Rem),
+                      ("&", BinaryOp::BitAnd), ("|", BinaryOp::BitOr), ("^", BinaryOp::BitXor)], Parser::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.accept(&["!", "-"]) {
+            Some("!") => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(_) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        if self.accept(&["("]).is_some() {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        let expr = match token {
+            Some(Token::Number(value)) => Expr::Number(value),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "i" => Expr::Register(Operand::I),
+                "pc" => Expr::Register(Operand::Pc),
+                "sp" => Expr::Register(Operand::Sp),
+                "dt" => Expr::Register(Operand::Dt),
+                "st" => Expr::Register(Operand::St),
+                "mem" => {
+                    self.pos += 1;
+                    self.expect("[")?;
+                    let addr = self.or()?;
+                    self.expect("]")?;
+                    return Ok(Expr::Memory(Box::new(addr)));
+                }
+                _ => match name.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+                    Some(Ok(x)) if name.len() == 2 => Expr::Register(Operand::V(x)),
+                    _ => return Err(self.unexpected("expected a register, mem[...] or a number")),
+                },
+            },
+            _ => return Err(self.unexpected("expected a register, mem[...] or a number")),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+#[test]
+fn condition_parse_and_eval() {
+    let mut regs = Registers::new();
+    let mut memory = vec![0u8; 0x400];
+    regs.v[3] = 0x10;
+    regs.i = 0x301;
+    memory[0x3F0] = 7;
+
+    let holds = |s: &str, regs: &Registers, memory: &[u8]| s.parse::<Condition>().unwrap().holds(regs, memory);
+    assert!(holds("v3 == 0x10 && i > 0x300", &regs, &memory));
+    assert!(!holds("v3 == 0x10 && i > 0x301", &regs, &memory));
+    assert!(holds("mem[0x3F0] != 0", &regs, &memory));
+    assert!(holds("mem[i + 0xEF] == 7 || v0", &regs, &memory));
+    assert!(holds("(1 + 2) * 3 == 9 && 1 + 2 * 3 == 7", &regs, &memory));
+    assert!(holds("!(vF & 1) && -1 < 0 && mem[0x10000] == 0 && 1 / 0 == 0", &regs, &memory));
+    assert_eq!("pc + 2".parse::<Condition>().unwrap().eval(&regs, &memory), 0x202);
+    assert_eq!("v3 ==".parse::<Condition>(), Err("Unexpected end of condition, expected a register, mem[...] or a number".to_string()));
+    assert_eq!("v3 == 1 )".parse::<Condition>(), Err("Unexpected ) at column 9".to_string()));
+    assert_eq!("vg == 1".parse::<Condition>(),
+               Err("Unexpected vg at column 1, expected a register, mem[...] or a number".to_string()));
+    memory[0x3F0] = 0;
+    assert!(!holds("mem[0x3F0] != 0", &regs, &memory));
+}
@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chip8::{Condition, Registers};
+
+/// Why execution stopped before the end of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// PC reached a breakpoint address
+    Breakpoint(u16),
+    /// the last instruction accessed memory covered by a watchpoint
+    Watchpoint { access: Access, addr: usize, value: u8 },
+    /// the last instruction set a register to the value of a register breakpoint
+    Register { reg: Register, value: usize },
+    /// the user asked to break, see `Command::Break`
+    Interrupted,
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(addr) => write!(f, "Breakpoint at {:03X}", addr),
+            BreakReason::Watchpoint { access: Access::Read, addr, value } =>
+                write!(f, "Watchpoint: read {:02X} from {:03X}", value, addr),
+            BreakReason::Watchpoint { access: Access::Write, addr, value } =>
+                write!(f, "Watchpoint: wrote {:02X} to {:03X}", value, addr),
+            BreakReason::Register { reg, value } => write!(f, "Register breakpoint: {} = {:X}", reg, value),
+            BreakReason::Interrupted => write!(f, "Interrupted"),
+        }
+    }
+}
+
+/// Breakpoint on an instruction address
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Breakpoint {
+    /// only break when the condition holds
+    pub condition: Option<Condition>,
+    /// ignore the first `hit_count - 1` hits (with the condition holding), 0 or 1 break on every hit
+    pub hit_count: u32,
+    hits: u32,
+}
+
+impl Breakpoint {
+    pub fn new(condition: Option<Condition>, hit_count: u32) -> Breakpoint {
+        Breakpoint { condition, hit_count, hits: 0 }
+    }
+
+    /// Number of times PC reached the breakpoint with its condition holding
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.hit_count > 1 {
+            write!(f, "hits {} ", self.hit_count)?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, "if {} ", condition)?;
+        }
+        write!(f, "({} hits)", self.hits)
+    }
+}
+
+/// Kind of a memory access made by an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Accesses a watchpoint breaks on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        match self {
+            WatchKind::Read => access == Access::Read,
+            WatchKind::Write => access == Access::Write,
+            WatchKind::ReadWrite => true,
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "r" | "read" => Ok(WatchKind::Read),
+            "w" | "write" => Ok(WatchKind::Write),
+            "rw" | "access" => Ok(WatchKind::ReadWrite),
+            _ => Err(format!("Unknown watchpoint kind {} (expected r, w or rw)", s)),
+        }
+    }
+}
+
+/// Memory range `start..start + len` watched for `kind` accesses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub len: usize,
+    pub kind: WatchKind,
+}
+
+/// Register a register breakpoint can watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(usize),
+    I,
+}
+
+impl Register {
+    pub fn value(self, regs: &Registers) -> usize {
+        match self {
+            Register::V(x) => regs.v[x] as usize,
+            Register::I => regs.i,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        if name == "i" {
+            return Ok(Register::I);
+        }
+        match name.strip_prefix('v').map(|x| usize::from_str_radix(x, 16)) {
+            Some(Ok(x)) if name.len() == 2 => Ok(Register::V(x)),
+            _ => Err(format!("Unknown register {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+        }
+    }
+}
+
+/// Breakpoints checked before each instruction by `Chip8::run_cycles()`,
+/// watchpoints and register breakpoints checked after it
+pub struct Debugger {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// break once the register changes to the value
+    register_breakpoints: Vec<(Register, usize)>,
+    break_reason: Option<BreakReason>,
+    /// the instruction at this address is executed without checking breakpoints
+    /// when execution resumes, so that it can continue from a breakpoint
+    resume_pc: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            watchpoints: Vec::new(),
+            register_breakpoints: Vec::new(),
+            break_reason: None,
+            resume_pc: None,
+        }
+    }
+
+    /// Break every time PC reaches `addr`, returns false if a breakpoint was already set on it
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.set_breakpoint(addr, Breakpoint::default())
+    }
+
+    /// Set or replace the breakpoint on `addr`, returns false if one was replaced
+    pub fn set_breakpoint(&mut self, addr: u16, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(addr, breakpoint).is_none()
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item=(&u16, &Breakpoint)> {
+        self.breakpoints.iter()
+    }
+
+    /// Watch `len` bytes starting at `start`, replacing any watchpoint starting at the same address
+    pub fn add_watchpoint(&mut self, start: usize, len: usize, kind: WatchKind) {
+        self.remove_watchpoint(start);
+        self.watchpoints.push(Watchpoint { start, len, kind });
+    }
+
+    pub fn remove_watchpoint(&mut self, start: usize) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|watchpoint| watchpoint.start != start);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Break after an instruction changes `reg` to `value`, replacing any breakpoint on `reg`
+    pub fn add_register_breakpoint(&mut self, reg: Register, value: usize) {
+        self.remove_register_breakpoint(reg);
+        self.register_breakpoints.push((reg, value));
+    }
+
+    pub fn remove_register_breakpoint(&mut self, reg: Register) -> bool {
+        let count = self.register_breakpoints.len();
+        self.register_breakpoints.retain(|(watched, _)| *watched != reg);
+        self.register_breakpoints.len() != count
+    }
+
+    pub fn register_breakpoints(&self) -> &[(Register, usize)] {
+        &self.register_breakpoints
+    }
+
+    /// Returns true if register breakpoints need the registers from before each instruction
+    pub fn watches_registers(&self) -> bool {
+        !self.register_breakpoints.is_empty()
+    }
+
+    pub fn break_reason(&self) -> Option<BreakReason> {
+        self.break_reason
+    }
+
+    pub fn set_break(&mut self, reason: BreakReason) {
+        self.break_reason = Some(reason);
+    }
+
+    /// Forget the last break and resume execution at `pc`
+    pub fn resume(&mut self, pc: u16) {
+        self.break_reason = None;
+        self.resume_pc = Some(pc);
+    }
+
+    /// Check breakpoints before executing the instruction at PC, returns true to break
+    pub fn check(&mut self, regs: &Registers, memory: &[u8]) -> bool {
+        let pc = regs.pc;
+        if self.break_reason.is_some() {
+            return true;
+        }
+        if self.resume_pc.take() == Some(pc) {
+            return false;
+        }
+        let breakpoint = match self.breakpoints.get_mut(&pc) {
+            Some(breakpoint) => breakpoint,
+            None => return false,
+        };
+        if let Some(condition) = &breakpoint.condition {
+            if !condition.holds(regs, memory) {
+                return false;
+            }
+        }
+        breakpoint.hits += 1;
+        if breakpoint.hits < breakpoint.hit_count {
+            return false;
+        }
+        self.break_reason = Some(BreakReason::Breakpoint(pc));
+        true
+    }
+
+    /// Hook called by the instrumented memory accessors for every byte an instruction reads or writes
+    pub fn memory_access(&mut self, access: Access, addr: usize, value: u8) {
+        if self.break_reason.is_some() {
+            return;
+        }
+        let hit = self.watchpoints.iter().any(|watchpoint| {
+            watchpoint.kind.matches(access) && addr >= watchpoint.start && addr - watchpoint.start < watchpoint.len
+        });
+        if hit {
+            self.break_reason = Some(BreakReason::Watchpoint { access, addr, value });
+        }
+    }
+
+    /// Check register breakpoints after an instruction, `before` holds the registers prior to it
+    pub fn check_registers(&mut self, before: &Registers, after: &Registers) {
+        if self.break_reason.is_some() {
+            return;
+        }
+        let hit = self.register_breakpoints.iter()
+            .find(|(reg, value)| reg.value(after) == *value && reg.value(before) != *value);
+        if let Some((reg, value)) = hit {
+            self.break_reason = Some(BreakReason::Register { reg: *reg, value: *value });
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
@@ -0,0 +1,451 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// `Display` renders it in Cowgod syntax (`DRW V1, V2, 5`), `octo()` in Octo syntax (`sprite v1 v2 5`).
+/// Words that are not instructions decode to `Unknown`, usually sprite or other data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00Cn (SUPER-CHIP)
+    Scd { n: u8 },
+    /// 00Dn (XO-CHIP)
+    Scu { n: u8 },
+    /// 00FB (SUPER-CHIP)
+    Scr,
+    /// 00FC (SUPER-CHIP)
+    Scl,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    Low,
+    /// 00FF (SUPER-CHIP)
+    High,
+    /// 0nnn - machine code routine, not supported by the interpreter
+    Sys { nnn: u16 },
+    /// 1nnn
+    Jp { nnn: u16 },
+    /// 2nnn
+    Call { nnn: u16 },
+    /// 3xkk
+    SeImm { x: u8, kk: u8 },
+    /// 4xkk
+    SneImm { x: u8, kk: u8 },
+    /// 5xy0
+    SeReg { x: u8, y: u8 },
+    /// 5xy2 (XO-CHIP)
+    SaveRange { x: u8, y: u8 },
+    /// 5xy3 (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// 6xkk
+    LdImm { x: u8, kk: u8 },
+    /// 7xkk
+    AddImm { x: u8, kk: u8 },
+    /// 8xy0
+    LdReg { x: u8, y: u8 },
+    /// 8xy1
+    Or { x: u8, y: u8 },
+    /// 8xy2
+    And { x: u8, y: u8 },
+    /// 8xy3
+    Xor { x: u8, y: u8 },
+    /// 8xy4
+    AddReg { x: u8, y: u8 },
+    /// 8xy5
+    Sub { x: u8, y: u8 },
+    /// 8xy6
+    Shr { x: u8, y: u8 },
+    /// 8xy7
+    Subn { x: u8, y: u8 },
+    /// 8xyE
+    Shl { x: u8, y: u8 },
+    /// 9xy0
+    SneReg { x: u8, y: u8 },
+    /// Annn
+    LdI { nnn: u16 },
+    /// Bnnn
+    JpV0 { nnn: u16 },
+    /// Cxkk
+    Rnd { x: u8, kk: u8 },
+    /// Dxyn, 16x16 sprite when n is 0 (SUPER-CHIP)
+    Drw { x: u8, y: u8, n: u8 },
+    /// Ex9E
+    Skp { x: u8 },
+    /// ExA1
+    Sknp { x: u8 },
+    /// F000 nnnn (XO-CHIP), the only 4-byte instruction
+    LdILong { nnnn: u16 },
+    /// F002 (XO-CHIP)
+    Audio,
+    /// Fn01 (XO-CHIP)
+    Plane { n: u8 },
+    /// Fx07
+    LdVxDt { x: u8 },
+    /// Fx0A
+    LdVxK { x: u8 },
+    /// Fx15
+    LdDtVx { x: u8 },
+    /// Fx18
+    LdStVx { x: u8 },
+    /// Fx1E
+    AddI { x: u8 },
+    /// Fx29
+    LdF { x: u8 },
+    /// Fx30 (SUPER-CHIP)
+    LdHf { x: u8 },
+    /// Fx33
+    LdB { x: u8 },
+    /// Fx3A (XO-CHIP)
+    Pitch { x: u8 },
+    /// Fx55
+    Save { x: u8 },
+    /// Fx65
+    Load { x: u8 },
+    /// Fx75 (SUPER-CHIP)
+    SaveFlags { x: u8 },
+    /// Fx85 (SUPER-CHIP)
+    LoadFlags { x: u8 },
+    /// not an instruction
+    Unknown(u16),
+}
+
+impl Instruction {
+    /// Decode an instruction word. The address of `F000 nnnn` is the next word,
+    /// it is left to 0, see `read()`.
+    pub fn decode(word: u16) -> Instruction {
+        let nnn = word & 0xFFF;
+        let x = ((word >> 8) & 0xF) as u8;
+        let y = ((word >> 4) & 0xF) as u8;
+        let n = (word & 0xF) as u8;
+        let kk = (word & 0xFF) as u8;
+
+        match (word >> 12, x, y, n) {
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xC, n) => Instruction::Scd { n },
+            (0, 0, 0xD, n) => Instruction::Scu { n },
+            (0, 0, 0xF, 0xB) => Instruction::Scr,
+            (0, 0, 0xF, 0xC) => Instruction::Scl,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Low,
+            (0, 0, 0xF, 0xF) => Instruction::High,
+            (0, ..) => Instruction::Sys { nnn },
+            (1, ..) => Instruction::Jp { nnn },
+            (2, ..) => Instruction::Call { nnn },
+            (3, ..) => Instruction::SeImm { x, kk },
+            (4, ..) => Instruction::SneImm { x, kk },
+            (5, _, _, 0) => Instruction::SeReg { x, y },
+            (5, _, _, 2) => Instruction::SaveRange { x, y },
+            (5, _, _, 3) => Instruction::LoadRange { x, y },
+            (6, ..) => Instruction::LdImm { x, kk },
+            (7, ..) => Instruction::AddImm { x, kk },
+            (8, _, _, 0) => Instruction::LdReg { x, y },
+            (8, _, _, 1) => Instruction::Or { x, y },
+            (8, _, _, 2) => Instruction::And { x, y },
+            (8, _, _, 3) => Instruction::Xor { x, y },
+            (8, _, _, 4) => Instruction::AddReg { x, y },
+            (8, _, _, 5) => Instruction::Sub { x, y },
+            (8, _, _, 6) => Instruction::Shr { x, y },
+            (8, _, _, 7) => Instruction::Subn { x, y },
+            (8, _, _, 0xE) => Instruction::Shl { x, y },
+            (9, _, _, 0) => Instruction::SneReg { x, y },
+            (0xA, ..) => Instruction::LdI { nnn },
+            (0xB, ..) => Instruction::JpV0 { nnn },
+            (0xC, ..) => Instruction::Rnd { x, kk },
+            (0xD, ..) => Instruction::Drw { x, y, n },
+            (0xE, _, 9, 0xE) => Instruction::Skp { x },
+            (0xE, _, 0xA, 1) => Instruction::Sknp { x },
+            (0xF, 0, 0, 0) => Instruction::LdILong { nnnn: 0 },
+            (0xF, 0, 0, 2) => Instruction::Audio,
+            (0xF, n, 0, 1) if n <= 3 => Instruction::Plane { n },
+            (0xF, _, 0, 7) => Instruction::LdVxDt { x },
+            (0xF, _, 0, 0xA) => Instruction::LdVxK { x },
+            (0xF, _, 1, 5) => Instruction::LdDtVx { x },
+            (0xF, _, 1, 8) => Instruction::LdStVx { x },
+            (0xF, _, 1, 0xE) => Instruction::AddI { x },
+            (0xF, _, 2, 9) => Instruction::LdF { x },
+            (0xF, _, 3, 0) => Instruction::LdHf { x },
+            (0xF, _, 3, 3) => Instruction::LdB { x },
+            (0xF, _, 3, 0xA) => Instruction::Pitch { x },
+            (0xF, _, 5, 5) => Instruction::Save { x },
+            (0xF, _, 6, 5) => Instruction::Load { x },
+            (0xF, _, 7, 5) => Instruction::SaveFlags { x },
+            (0xF, _, 8, 5) => Instruction::LoadFlags { x },
+            _ => Instruction::Unknown(word),
+        }
+    }
+
+    /// Decode the instruction at `addr` in `memory`, including the address of `F000 nnnn`.
+    /// Bytes past the end of memory read as 0.
+    pub fn read(memory: &[u8], addr: usize) -> Instruction {
+        let word = |addr: usize| {
+            let byte = |addr: usize| *memory.get(addr).unwrap_or(&0) as u16;
+            (byte(addr) << 8) | byte(addr + 1)
+        };
+        match Instruction::decode(word(addr)) {
+            Instruction::LdILong { .. } => Instruction::LdILong { nnnn: word(addr + 2) },
+            instr => instr,
+        }
+    }
+
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LdILong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    /// Encode back into an instruction word, followed by the address word of `F000 nnnn`
+    pub fn encode(&self) -> (u16, Option<u16>) {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: u8, kk: u8| op << 12 | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, kk: u16| 0xF000 | (x as u16) << 8 | kk;
+
+        let word = match *self {
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Scd { n } => 0x00C0 | n as u16,
+            Instruction::Scu { n } => 0x00D0 | n as u16,
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Sys { nnn } => nnn,
+            Instruction::Jp { nnn } => 0x1000 | nnn,
+            Instruction::Call { nnn } => 0x2000 | nnn,
+            Instruction::SeImm { x, kk } => xkk(3, x, kk),
+            Instruction::SneImm { x, kk } => xkk(4, x, kk),
+            Instruction::SeReg { x, y } => xy(5, x, y, 0),
+            Instruction::SaveRange { x, y } => xy(5, x, y, 2),
+            Instruction::LoadRange { x, y } => xy(5, x, y, 3),
+            Instruction::LdImm { x, kk } => xkk(6, x, kk),
+            Instruction::AddImm { x, kk } => xkk(7, x, kk),
+            Instruction::LdReg { x, y } => xy(8, x, y, 0),
+            Instruction::Or { x, y } => xy(8, x, y, 1),
+            Instruction::And { x, y } => xy(8, x, y, 2),
+            Instruction::Xor { x, y } => xy(8, x, y, 3),
+            Instruction::AddReg { x, y } => xy(8, x, y, 4),
+            Instruction::Sub { x, y } => xy(8, x, y, 5),
+            Instruction::Shr { x, y } => xy(8, x, y, 6),
+            Instruction::Subn { x, y } => xy(8, x, y, 7),
+            Instruction::Shl { x, y } => xy(8, x, y, 0xE),
+            Instruction::SneReg { x, y } => xy(9, x, y, 0),
+            Instruction::LdI { nnn } => 0xA000 | nnn,
+            Instruction::JpV0 { nnn } => 0xB000 | nnn,
+            Instruction::Rnd { x, kk } => xkk(0xC, x, kk),
+            Instruction::Drw { x, y, n } => xy(0xD, x, y, n as u16),
+            Instruction::Skp { x } => xkk(0xE, x, 0x9E),
+            Instruction::Sknp { x } => xkk(0xE, x, 0xA1),
+            Instruction::LdILong { nnnn } => return (0xF000, Some(nnnn)),
+            Instruction::Audio => 0xF002,
+            Instruction::Plane { n } => fx(n, 0x01),
+            Instruction::LdVxDt { x } => fx(x, 0x07),
+            Instruction::LdVxK { x } => fx(x, 0x0A),
+            Instruction::LdDtVx { x } => fx(x, 0x15),
+            Instruction::LdStVx { x } => fx(x, 0x18),
+            Instruction::AddI { x } => fx(x, 0x1E),
+            Instruction::LdF { x } => fx(x, 0x29),
+            Instruction::LdHf { x } => fx(x, 0x30),
+            Instruction::LdB { x } => fx(x, 0x33),
+            Instruction::Pitch { x } => fx(x, 0x3A),
+            Instruction::Save { x } => fx(x, 0x55),
+            Instruction::Load { x } => fx(x, 0x65),
+            Instruction::SaveFlags { x } => fx(x, 0x75),
+            Instruction::LoadFlags { x } => fx(x, 0x85),
+            Instruction::Unknown(word) => word,
+        };
+        (word, None)
+    }
+
+    /// Address this instruction jumps to or calls, if any
+    pub fn target(&self) -> Option<u16> {
+        match *self {
+            Instruction::Jp { nnn } | Instruction::Call { nnn } | Instruction::JpV0 { nnn } => Some(nnn),
+            _ => None,
+        }
+    }
+
+    /// Returns true for instructions skipping the next one on a condition
+    pub fn is_skip(&self) -> bool {
+        matches!(self, Instruction::SeImm { .. } | Instruction::SneImm { .. } | Instruction::SeReg { .. } |
+                       Instruction::SneReg { .. } | Instruction::Skp { .. } | Instruction::Sknp { .. })
+    }
+
+    /// Octo syntax rendering, e.g. `sprite v1 v2 5` for `DRW V1, V2, 5`
+    pub fn octo(&self) -> Octo<'_> {
+        Octo(self)
+    }
+}
+
+impl From<u16> for Instruction {
+    fn from(word: u16) -> Self {
+        Instruction::decode(word)
+    }
+}
+
+/// Cowgod syntax
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scd { n } => write!(f, "SCD {}", n),
+            Instruction::Scu { n } => write!(f, "SCU {}", n),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Sys { nnn } => write!(f, "SYS #{:03X}", nnn),
+            Instruction::Jp { nnn } => write!(f, "JP #{:03X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL #{:03X}", nnn),
+            Instruction::SeImm { x, kk } => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            Instruction::SneImm { x, kk } => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            Instruction::SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::LdImm { x, kk } => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            Instruction::AddImm { x, kk } => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Instruction::LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI { nnn } => write!(f, "LD I, #{:03X}", nnn),
+            Instruction::JpV0 { nnn } => write!(f, "JP V0, #{:03X}", nnn),
+            Instruction::Rnd { x, kk } => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Instruction::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp { x } => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong { nnnn } => write!(f, "LD I, LONG #{:04X}", nnnn),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::Plane { n } => write!(f, "PLANE {}", n),
+            Instruction::LdVxDt { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHf { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdB { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::Save { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::SaveFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(word) => write!(f, "DW #{:04X}", word),
+        }
+    }
+}
+
+/// Octo syntax rendering of an instruction, see `Instruction::octo()`.
+/// Skips are rendered as the `if ... then` condition executing the next instruction.
+pub struct Octo<'a>(&'a Instruction);
+
+impl fmt::Display for Octo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Instruction::Cls => write!(f, "clear"),
+            Instruction::Ret => write!(f, "return"),
+            Instruction::Scd { n } => write!(f, "scroll-down {}", n),
+            Instruction::Scu { n } => write!(f, "scroll-up {}", n),
+            Instruction::Scr => write!(f, "scroll-right"),
+            Instruction::Scl => write!(f, "scroll-left"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::Low => write!(f, "lores"),
+            Instruction::High => write!(f, "hires"),
+            Instruction::Sys { nnn } => write!(f, "0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+            Instruction::Jp { nnn } => write!(f, "jump 0x{:03X}", nnn),
+            Instruction::Call { nnn } => write!(f, ":call 0x{:03X}", nnn),
+            Instruction::SeImm { x, kk } => write!(f, "if v{:x} != 0x{:02X} then", x, kk),
+            Instruction::SneImm { x, kk } => write!(f, "if v{:x} == 0x{:02X} then", x, kk),
+            Instruction::SeReg { x, y } => write!(f, "if v{:x} != v{:x} then", x, y),
+            Instruction::SaveRange { x, y } => write!(f, "save v{:x} - v{:x}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "load v{:x} - v{:x}", x, y),
+            Instruction::LdImm { x, kk } => write!(f, "v{:x} := 0x{:02X}", x, kk),
+            Instruction::AddImm { x, kk } => write!(f, "v{:x} += 0x{:02X}", x, kk),
+            Instruction::LdReg { x, y } => write!(f, "v{:x} := v{:x}", x, y),
+            Instruction::Or { x, y } => write!(f, "v{:x} |= v{:x}", x, y),
+            Instruction::And { x, y } => write!(f, "v{:x} &= v{:x}", x, y),
+            Instruction::Xor { x, y } => write!(f, "v{:x} ^= v{:x}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "v{:x} += v{:x}", x, y),
+            Instruction::Sub { x, y } => write!(f, "v{:x} -= v{:x}", x, y),
+            Instruction::Shr { x, y } => write!(f, "v{:x} >>= v{:x}", x, y),
+            Instruction::Subn { x, y } => write!(f, "v{:x} =- v{:x}", x, y),
+            Instruction::Shl { x, y } => write!(f, "v{:x} <<= v{:x}", x, y),
+            Instruction::SneReg { x, y } => write!(f, "if v{:x} == v{:x} then", x, y),
+            Instruction::LdI { nnn } => write!(f, "i := 0x{:03X}", nnn),
+            Instruction::JpV0 { nnn } => write!(f, "jump0 0x{:03X}", nnn),
+            Instruction::Rnd { x, kk } => write!(f, "v{:x} := random 0x{:02X}", x, kk),
+            Instruction::Drw { x, y, n } => write!(f, "sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::Skp { x } => write!(f, "if v{:x} -key then", x),
+            Instruction::Sknp { x } => write!(f, "if v{:x} key then", x),
+            Instruction::LdILong { nnnn } => write!(f, "i := long 0x{:04X}", nnnn),
+            Instruction::Audio => write!(f, "audio"),
+            Instruction::Plane { n } => write!(f, "plane {}", n),
+            Instruction::LdVxDt { x } => write!(f, "v{:x} := delay", x),
+            Instruction::LdVxK { x } => write!(f, "v{:x} := key", x),
+            Instruction::LdDtVx { x } => write!(f, "delay := v{:x}", x),
+            Instruction::LdStVx { x } => write!(f, "buzzer := v{:x}", x),
+            Instruction::AddI { x } => write!(f, "i += v{:x}", x),
+            Instruction::LdF { x } => write!(f, "i := hex v{:x}", x),
+            Instruction::LdHf { x } => write!(f, "i := bighex v{:x}", x),
+            Instruction::LdB { x } => write!(f, "bcd v{:x}", x),
+            Instruction::Pitch { x } => write!(f, "pitch := v{:x}", x),
+            Instruction::Save { x } => write!(f, "save v{:x}", x),
+            Instruction::Load { x } => write!(f, "load v{:x}", x),
+            Instruction::SaveFlags { x } => write!(f, "saveflags v{:x}", x),
+            Instruction::LoadFlags { x } => write!(f, "loadflags v{:x}", x),
+            Instruction::Unknown(word) => write!(f, "0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+        }
+    }
+}
+
+#[test]
+fn disassemble_instructions() {
+    let cases = [
+        (0x00E0, "CLS", "clear"),
+        (0x00C4, "SCD 4", "scroll-down 4"),
+        (0x2ABC, "CALL #ABC", ":call 0xABC"),
+        (0x3A12, "SE VA, #12", "if va != 0x12 then"),
+        (0x5123, "LD V1-V2, [I]", "load v1 - v2"),
+        (0x8124, "ADD V1, V2", "v1 += v2"),
+        (0x8127, "SUBN V1, V2", "v1 =- v2"),
+        (0xB300, "JP V0, #300", "jump0 0x300"),
+        (0xD125, "DRW V1, V2, 5", "sprite v1 v2 5"),
+        (0xE3A1, "SKNP V3", "if v3 key then"),
+        (0xF201, "PLANE 2", "plane 2"),
+        (0xF330, "LD HF, V3", "i := bighex v3"),
+        (0xF365, "LD V3, [I]", "load v3"),
+        (0x5121, "DW #5121", "0x51 0x21"),
+        (0xF401, "DW #F401", "0xF4 0x01"),
+    ];
+    for (word, cowgod, octo) in cases.iter() {
+        let instr = Instruction::decode(*word);
+        assert_eq!(instr.to_string(), *cowgod);
+        assert_eq!(instr.octo().to_string(), *octo);
+    }
+    assert_eq!(Instruction::decode(0xD125), Instruction::Drw { x: 1, y: 2, n: 5 });
+}
+
+#[test]
+fn decode_encode_round_trip() {
+    for word in 0..=0xFFFFu16 {
+        assert_eq!(Instruction::decode(word).encode(), (word, if word == 0xF000 { Some(0) } else { None }));
+    }
+
+    let long = Instruction::read(&[0xF0, 0x00, 0x12, 0x34], 0);
+    assert_eq!(long, Instruction::LdILong { nnnn: 0x1234 });
+    assert_eq!((long.size(), long.to_string(), long.octo().to_string()), (4, "LD I, LONG #1234".to_string(), "i := long 0x1234".to_string()));
+    assert_eq!(Instruction::read(&[0x12], 0), Instruction::Jp { nnn: 0x200 });
+}
@@ -1,55 +1,168 @@
-use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::chip8::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
 
 pub struct Display {
     pixels: Vec<Vec<u8>>,
+    width: usize,
+    height: usize,
+    /// planes selected by Fn01
+    planes: u8,
 }
 
+/// Both XO-CHIP planes
+pub const ALL_PLANES: u8 = 0b11;
+
 pub struct Sprite {
-    pixels: Vec<u8>,
+    /// one row per entry, most significant bit is the leftmost pixel
+    pixels: Vec<u16>,
+    width: usize,
     height: usize,
 }
 
 impl Sprite {
+    /// 8 pixels wide sprite, one byte per row
     pub fn new(sprite: Vec<u8>) -> Sprite {
         //assert!(sprite.len() > 1);
         assert!(sprite.len() < 16);
 
         Sprite {
-            pixels: sprite.clone(),
+            pixels: sprite.iter().map(|row| *row as u16).collect(),
+            width: 8,
             height: sprite.len(),
         }
     }
+
+    /// SUPER-CHIP 16x16 sprite, two bytes per row
+    pub fn new_large(sprite: Vec<u8>) -> Sprite {
+        assert_eq!(sprite.len(), 32);
+
+        Sprite {
+            pixels: sprite.chunks(2).map(|row| ((row[0] as u16) << 8) | row[1] as u16).collect(),
+            width: 16,
+            height: 16,
+        }
+    }
 }
 
 impl Display {
     pub fn new() -> Display {
         Display {
             pixels: vec![vec![0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            planes: 1,
         }
     }
 
+    /// Clear the selected planes
     pub fn clear(&mut self) {
+        let mask = !self.planes;
         for row in &mut self.pixels {
             for pixel in row {
-                *pixel = 0;
+                *pixel &= mask;
             }
         }
     }
 
+    /// Pixels as a plane bitmask: bit 0 is set if the pixel is on in plane 1, bit 1 for plane 2
     pub fn pixels(&self) -> &Vec<Vec<u8>> {
         &self.pixels
     }
 
-    pub fn draw_sprite(&mut self, sprite: &Sprite, x: usize, y: usize) -> bool {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Bitmask of the planes affected by clear, scroll and draw (XO-CHIP)
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    /// Overwrite all pixels, `pixels` holds width * height plane bitmasks row by row
+    pub fn restore_pixels(&mut self, pixels: &[u8]) {
+        for (row, values) in self.pixels.iter_mut().zip(pixels.chunks(self.width)) {
+            row.copy_from_slice(values);
+        }
+    }
+
+    /// Switch between the 64x32 and the SUPER-CHIP 128x64 resolution, clears all planes
+    pub fn set_hires(&mut self, hires: bool) {
+        if hires {
+            self.width = HIRES_DISPLAY_WIDTH;
+            self.height = HIRES_DISPLAY_HEIGHT;
+        } else {
+            self.width = DISPLAY_WIDTH;
+            self.height = DISPLAY_HEIGHT;
+        }
+        self.pixels = vec![vec![0; self.width]; self.height];
+    }
+
+    /// Scroll the selected planes down by n pixels
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    /// Scroll the selected planes up by n pixels (XO-CHIP)
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    /// Scroll the selected planes right by n pixels
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    /// Scroll the selected planes left by n pixels
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    /// Move the selected planes by (dx, dy), pixels shifted in from outside the screen are off
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let mask = self.planes;
+        let source = self.pixels.clone();
+        for (row, pixels) in self.pixels.iter_mut().enumerate() {
+            for (col, pixel) in pixels.iter_mut().enumerate() {
+                let src_row = row as isize - dy;
+                let src_col = col as isize - dx;
+                let shifted = if src_row >= 0 && src_row < self.height as isize && src_col >= 0 && src_col < self.width as isize {
+                    source[src_row as usize][src_col as usize] & mask
+                } else {
+                    0
+                };
+                *pixel = (*pixel & !mask) | shifted;
+            }
+        }
+    }
+
+    /// Draw a sprite on a single plane (bitmask 1 or 2) with its top-left corner at (x, y),
+    /// returns true on collision.
+    /// The origin wraps around the screen, the sprite itself is clipped at the edges
+    /// if `clip` is true and wraps around otherwise.
+    pub fn draw_sprite(&mut self, sprite: &Sprite, x: usize, y: usize, clip: bool, plane: u8) -> bool {
+        let (x, y) = (x % self.width, y % self.height);
         let mut collision = false;
         for row_index in 0..sprite.height {
-            let row = (y + row_index) % DISPLAY_HEIGHT;
-            for col_index in 0..8 {
-                let col = (x + col_index) % DISPLAY_WIDTH;
+            if clip && y + row_index >= self.height {
+                break;
+            }
+            let row = (y + row_index) % self.height;
+            for col_index in 0..sprite.width {
+                if clip && x + col_index >= self.width {
+                    break;
+                }
+                let col = (x + col_index) % self.width;
                 let pixel = self.pixels[row][col];
-                let new_pixel = (sprite.pixels[row_index] & (128u8 >> (col_index as u8))) >> (7 - col_index) as u8;
+                let new_pixel = ((sprite.pixels[row_index] >> (sprite.width - 1 - col_index)) & 1) as u8 * plane;
 
-                if pixel == 1 && new_pixel == 1 {
+                if pixel & new_pixel != 0 {
                     collision = true;
                 }
 
@@ -64,7 +177,7 @@ impl Display {
 #[test]
 fn display_is_blank_at_init() {
     let d = Display::new();
-    for byte in d.pixels().into_iter().flatten() {
+    for byte in d.pixels().iter().flatten() {
         assert_eq!(*byte, 0);
     }
 }
@@ -74,11 +187,93 @@ fn clear_display() {
     let mut d = Display::new();
     d.pixels = vec![vec![0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
     d.clear();
-    for byte in d.pixels().into_iter().flatten() {
+    for byte in d.pixels().iter().flatten() {
         assert_eq!(*byte, 0)
     }
 }
 
+#[test]
+fn draw_sprite_wraps_or_clips() {
+    let sprite = Sprite::new(vec![0xFF, 0xFF]);
+
+    let mut d = Display::new();
+    assert!(!d.draw_sprite(&sprite, DISPLAY_WIDTH - 4, DISPLAY_HEIGHT - 1, false, 1));
+    assert_eq!(d.pixels()[DISPLAY_HEIGHT - 1][DISPLAY_WIDTH - 1], 1);
+    assert_eq!(d.pixels()[0][0], 1);
+    assert_eq!(d.pixels()[0][3], 1);
+    assert_eq!(d.pixels()[0][4], 0);
+
+    let mut d = Display::new();
+    assert!(!d.draw_sprite(&sprite, DISPLAY_WIDTH - 4, DISPLAY_HEIGHT - 1, true, 1));
+    assert_eq!(d.pixels()[DISPLAY_HEIGHT - 1][DISPLAY_WIDTH - 1], 1);
+    assert_eq!(d.pixels()[DISPLAY_HEIGHT - 1][0], 0);
+    assert_eq!(d.pixels()[0][0], 0);
+
+    // origin always wraps
+    assert!(d.draw_sprite(&sprite, DISPLAY_WIDTH * 2 - 1, DISPLAY_HEIGHT - 1, true, 1));
+    assert_eq!(d.pixels()[DISPLAY_HEIGHT - 1][DISPLAY_WIDTH - 1], 0);
+}
+
+#[test]
+fn hires_and_scroll() {
+    let mut d = Display::new();
+    d.set_hires(true);
+    assert_eq!(d.pixels().len(), HIRES_DISPLAY_HEIGHT);
+    assert_eq!(d.pixels()[0].len(), HIRES_DISPLAY_WIDTH);
+
+    let sprite = Sprite::new_large(vec![0xFF; 32]);
+    d.draw_sprite(&sprite, 0, 0, true, 1);
+    assert_eq!(d.pixels()[15][15], 1);
+    assert_eq!(d.pixels()[16][16], 0);
+
+    d.scroll_down(2);
+    assert_eq!(d.pixels()[1][0], 0);
+    assert_eq!(d.pixels()[17][0], 1);
+    assert_eq!(d.pixels()[18][0], 0);
+
+    d.scroll_right(4);
+    assert_eq!(d.pixels()[2][3], 0);
+    assert_eq!(d.pixels()[2][19], 1);
+    assert_eq!(d.pixels()[2][20], 0);
+
+    d.scroll_left(4);
+    assert_eq!(d.pixels()[2][0], 1);
+    assert_eq!(d.pixels()[2][16], 0);
+
+    d.set_hires(false);
+    assert_eq!(d.pixels().len(), DISPLAY_HEIGHT);
+    assert!(d.pixels().iter().flatten().all(|pixel| *pixel == 0));
+}
+
+#[test]
+fn planes() {
+    let mut d = Display::new();
+    let sprite = Sprite::new(vec![0x80]);
+    d.draw_sprite(&sprite, 0, 0, false, 1);
+    d.draw_sprite(&sprite, 0, 0, false, 2);
+    d.draw_sprite(&sprite, 1, 0, false, 2);
+    assert_eq!(d.pixels()[0][0], 3);
+    assert_eq!(d.pixels()[0][1], 2);
+
+    d.select_planes(2);
+    d.scroll_down(1);
+    assert_eq!(d.pixels()[0][0], 1);
+    assert_eq!(d.pixels()[1][0], 2);
+    assert_eq!(d.pixels()[1][1], 2);
+
+    d.scroll_up(1);
+    assert_eq!(d.pixels()[0][0], 3);
+    assert_eq!(d.pixels()[1][0], 0);
+
+    d.clear();
+    assert_eq!(d.pixels()[0][0], 1);
+    assert_eq!(d.pixels()[0][1], 0);
+
+    d.select_planes(0);
+    assert!(!d.draw_sprite(&sprite, 0, 0, false, 0));
+    assert_eq!(d.pixels()[0][0], 1);
+}
+
 pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0,
     0x20, 0x60, 0x20, 0x20, 0x70,
@@ -97,3 +292,23 @@ pub const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0,
     0xF0, 0x80, 0xF0, 0x80, 0x80,
 ];
+
+/// SUPER-CHIP 8x10 font for digits 0-F, stored right after FONT
+pub const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C,
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C,
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF,
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C,
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C,
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C,
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60,
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C,
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C,
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC,
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C,
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xFF, 0xFF,
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFC, 0xC0, 0xC0, 0xC0, 0xC0,
+];
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt;
+
+/// Error raised by the emulator core when an instruction cannot be executed.
+///
+/// Every variant carries the address of the faulting instruction (`pc`) and the
+/// raw instruction word (`instr`) so that a frontend can report where a ROM failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// CALL executed with a full call stack
+    StackOverflow { pc: u16, instr: u16 },
+    /// RET executed with an empty call stack
+    StackUnderflow { pc: u16, instr: u16 },
+    /// instruction word that does not decode to any known instruction
+    UnknownOpcode { pc: u16, instr: u16 },
+    /// SKP/SKNP executed with a Vx that is not a valid key (0x0-0xF)
+    InvalidKey { pc: u16, instr: u16, key: u8 },
+    /// LD F, Vx executed with a Vx that is not a hex digit (0x0-0xF)
+    InvalidDigit { pc: u16, instr: u16, digit: u8 },
+    /// memory access outside of the emulator address space
+    MemoryOutOfBounds { pc: u16, instr: u16, addr: usize },
+}
+
+impl EmulatorError {
+    /// Address of the faulting instruction
+    pub fn pc(&self) -> u16 {
+        match *self {
+            EmulatorError::StackOverflow { pc, .. }
+            | EmulatorError::StackUnderflow { pc, .. }
+            | EmulatorError::UnknownOpcode { pc, .. }
+            | EmulatorError::InvalidKey { pc, .. }
+            | EmulatorError::InvalidDigit { pc, .. }
+            | EmulatorError::MemoryOutOfBounds { pc, .. } => pc,
+        }
+    }
+
+    /// Raw faulting instruction word
+    pub fn instr(&self) -> u16 {
+        match *self {
+            EmulatorError::StackOverflow { instr, .. }
+            | EmulatorError::StackUnderflow { instr, .. }
+            | EmulatorError::UnknownOpcode { instr, .. }
+            | EmulatorError::InvalidKey { instr, .. }
+            | EmulatorError::InvalidDigit { instr, .. }
+            | EmulatorError::MemoryOutOfBounds { instr, .. } => instr,
+        }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::StackOverflow { pc, instr } =>
+                write!(f, "Stack overflow at {:03X} (instruction {:04X})", pc, instr),
+            EmulatorError::StackUnderflow { pc, instr } =>
+                write!(f, "Stack underflow at {:03X} (instruction {:04X})", pc, instr),
+            EmulatorError::UnknownOpcode { pc, instr } =>
+                write!(f, "Unknown instruction {:04X} at {:03X}", instr, pc),
+            EmulatorError::InvalidKey { pc, instr, key } =>
+                write!(f, "Instruction {:04X} at {:03X} executed with Vx ({:X}) > 0xF", instr, pc, key),
+            EmulatorError::InvalidDigit { pc, instr, digit } =>
+                write!(f, "Instruction {:04X} at {:03X}: Vx {:X} must be a digit not larger than 0xF", instr, pc, digit),
+            EmulatorError::MemoryOutOfBounds { pc, instr, addr } =>
+                write!(f, "Instruction {:04X} at {:03X} accessed memory out of bounds at {:X}", instr, pc, addr),
+        }
+    }
+}
+
+impl Error for EmulatorError {}
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+
+use crate::chip8::state::crc32;
+use crate::chip8::{Backend, Chip8, EmulatorError, Palette, KBD_SIZE};
+
+/// Keys held down while running without a frontend, as a list of `<frame>=<keys>` entries
+/// separated by commas or whitespace. `keys` are hex digits held from that frame until
+/// the next entry, empty to release every key: `30=5 35= 100=46`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    /// (first frame, keys held) sorted by frame
+    entries: Vec<(u32, [bool; KBD_SIZE])>,
+}
+
+impl KeyScript {
+    /// Hold `keys` from `frame` on
+    pub fn hold(&mut self, frame: u32, keys: &[u8]) {
+        let mut held = [false; KBD_SIZE];
+        for key in keys {
+            held[*key as usize & 0xF] = true;
+        }
+        let index = self.entries.iter().position(|(start, _)| *start >= frame).unwrap_or(self.entries.len());
+        if self.entries.get(index).is_some_and(|(start, _)| *start == frame) {
+            self.entries[index].1 = held;
+        } else {
+            self.entries.insert(index, (frame, held));
+        }
+    }
+
+    /// Keys held during `frame`
+    pub fn keys_at(&self, frame: u32) -> [bool; KBD_SIZE] {
+        self.entries.iter().rev()
+            .find(|(start, _)| *start <= frame)
+            .map_or([false; KBD_SIZE], |(_, keys)| *keys)
+    }
+}
+
+impl FromStr for KeyScript {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut script = KeyScript::default();
+        for entry in s.split(|c: char| c == ',' || c.is_whitespace()).filter(|entry| !entry.is_empty()) {
+            let invalid = || format!("Invalid key script entry {} (expected <frame>=<hex keys>)", entry);
+            let (frame, keys) = entry.split_at(entry.find('=').ok_or_else(invalid)?);
+            let frame = frame.parse::<u32>().map_err(|_| invalid())?;
+            let keys = keys[1..].chars()
+                .map(|c| c.to_digit(16).map(|key| key as u8).ok_or_else(invalid))
+                .collect::<Result<Vec<u8>, String>>()?;
+            script.hold(frame, &keys);
+        }
+        Ok(script)
+    }
+}
+
+impl<B: Backend> Chip8<B> {
+    /// Run `frames` frames as fast as possible with the keypad driven by `script`,
+    /// without polling nor rendering to the backend. Stops early once the program
+    /// exits or a breakpoint is hit. Returns the number of frames run.
+    pub fn run_headless(&mut self, frames: u32, script: &KeyScript) -> Result<u32, EmulatorError> {
+        for frame in 0..frames {
+            if self.exited || self.debugger.break_reason().is_some() {
+                return Ok(frame);
+            }
+            self.keypad.copy_from_slice(&script.keys_at(frame));
+            self.run_frame()?;
+        }
+        Ok(frames)
+    }
+
+    /// Current display, one `Vec<u8>` of plane bitmasks per row, see `Backend::render()`
+    pub fn pixels(&self) -> &[Vec<u8>] {
+        self.display.pixels()
+    }
+}
+
+/// CRC-32 of the display size and pixels, a stable fingerprint for snapshot tests
+pub fn framebuffer_hash(pixels: &[Vec<u8>]) -> u32 {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(pixels.first().map_or(0, Vec::len) as u16).to_be_bytes());
+    data.extend_from_slice(&(pixels.len() as u16).to_be_bytes());
+    for row in pixels {
+        data.extend_from_slice(row);
+    }
+    crc32(&data)
+}
+
+/// One character per pixel: `.` off, `#` plane 1, `+` plane 2, `@` both planes
+pub fn framebuffer_ascii(pixels: &[Vec<u8>]) -> String {
+    let mut ascii = String::new();
+    for row in pixels {
+        ascii.extend(row.iter().map(|pixel| ['.', '#', '+', '@'][*pixel as usize & 3]));
+        ascii.push('\n');
+    }
+    ascii
+}
+
+/// Plain PBM (P1) bitmap, pixels lit in any plane are black
+pub fn framebuffer_pbm(pixels: &[Vec<u8>]) -> Vec<u8> {
+    let mut pbm = format!("P1\n{} {}\n", pixels.first().map_or(0, Vec::len), pixels.len());
+    for row in pixels {
+        let bits: Vec<&str> = row.iter().map(|pixel| if *pixel != 0 { "1" } else { "0" }).collect();
+        pbm.push_str(&bits.join(" "));
+        pbm.push('\n');
+    }
+    pbm.into_bytes()
+}
+
+/// RGB PNG image colored with `palette`, each pixel drawn as a `scale` x `scale` square
+pub fn framebuffer_png(pixels: &[Vec<u8>], palette: &Palette, scale: usize) -> Vec<u8> {
+    let scale = scale.max(1);
+    let width = pixels.first().map_or(0, Vec::len) * scale;
+    let height = pixels.len() * scale;
+
+    // filter type 0 then RGB triples for each scanline
+    let mut raw = Vec::with_capacity(height * (1 + 3 * width));
+    for row in pixels {
+        let mut line = vec![0];
+        for pixel in row {
+            let (r, g, b) = palette[*pixel as usize & 3];
+            for _ in 0..scale {
+                line.extend_from_slice(&[r, g, b]);
+            }
+        }
+        for _ in 0..scale {
+            raw.extend_from_slice(&line);
+        }
+    }
+
+    // zlib stream made of stored (uncompressed) deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filter and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    for (kind, data) in [(b"IHDR", header), (b"IDAT", zlib), (b"IEND", Vec::new())].iter() {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(*kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    png
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[test]
+fn key_script_parse() {
+    let script: KeyScript = "30=5, 35= 100=4A".parse().unwrap();
+    assert_eq!(script.keys_at(0), [false; KBD_SIZE]);
+    assert!(script.keys_at(30)[5] && script.keys_at(34)[5]);
+    assert_eq!(script.keys_at(35), [false; KBD_SIZE]);
+    let keys = script.keys_at(1000);
+    assert_eq!(keys.iter().filter(|key| **key).count(), 2);
+    assert!(keys[4] && keys[0xA]);
+
+    assert!("30".parse::<KeyScript>().is_err());
+    assert!("x=1".parse::<KeyScript>().is_err());
+    assert!("1=G".parse::<KeyScript>().is_err());
+}
+
+#[test]
+fn framebuffer_formats() {
+    use crate::chip8::DEFAULT_PALETTE;
+
+    let pixels = vec![vec![0, 1, 2], vec![3, 0, 1]];
+    assert_eq!(framebuffer_ascii(&pixels), ".#+\n@.#\n");
+    assert_eq!(framebuffer_pbm(&pixels), b"P1\n3 2\n0 1 1\n1 0 1\n".to_vec());
+    assert_ne!(framebuffer_hash(&pixels), framebuffer_hash(&[vec![0, 1, 2, 3, 0, 1]]));
+
+    let png = framebuffer_png(&pixels, &DEFAULT_PALETTE, 2);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 6, 0, 0, 0, 4]);
+    assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn run_headless_test_rom_snapshot() {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(include_bytes!("../../roms/test.ch8").to_vec()).unwrap();
+    assert_eq!(chip8.run_headless(120, &KeyScript::default()), Ok(120));
+    let expected = "\
+................................................................
+.###.#.#..###.#.#......###.###..###.#.#.....###..##.###.#.#.....
+..##..#...#.#.##.......#.#.##...#.#.##......###..#..#.#.##......
+...#.#.#..#.#.#.#......#.#.#....#.#.#.#.....#.#...#.#.#.#.#.....
+.###.#.#..###.#.#......###.###..###.#.#.....###..#..###.#.#.....
+................................................................
+.#.#.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+.###..#...#.#.##.......###.#.#..#.#.##......###.#...#.#.##......
+...#.#.#..#.#.#.#......#.#.#.#..#.#.#.#.....#.#.###.#.#.#.#.....
+...#.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+................................................................
+..##.#.#..###.#.#......###.##...###.#.#.....###.###.###.#.#.....
+..#...#...#.#.##.......###..#...#.#.##......###.##..#.#.##......
+...#.#.#..#.#.#.#......#.#..#...#.#.#.#.....#.#.#...#.#.#.#.....
+..#..#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+................................................................
+.###.#.#..###.#.#......###.###..###.#.#.....###..##.###.#.#.....
+...#..#...#.#.##.......###...#..#.#.##......#....#..#.#.##......
+...#.#.#..#.#.#.#......#.#.##...#.#.#.#.....##....#.#.#.#.#.....
+...#.#.#..###.#.#......###.###..###.#.#.....#....#..###.#.#.....
+................................................................
+.###.#.#..###.#.#......###.###..###.#.#.....###.###.###.#.#.....
+.###..#...#.#.##.......###..##..#.#.##......#....##.#.#.##......
+...#.#.#..#.#.#.#......#.#...#..#.#.#.#.....##....#.#.#.#.#.....
+.###.#.#..###.#.#......###.###..###.#.#.....#...###.###.#.#.....
+................................................................
+..#..#.#..###.#.#......###.#.#..###.#.#.....##..#.#.###.#.#.....
+.#.#..#...#.#.##.......###.###..#.#.##.......#...#..#.#.##......
+.###.#.#..#.#.#.#......#.#...#..#.#.#.#......#..#.#.#.#.#.#.....
+.#.#.#.#..###.#.#......###...#..###.#.#.....###.#.#.###.#.#.....
+................................................................
+................................................................
+";
+    assert_eq!(framebuffer_ascii(chip8.pixels()), expected);
+    assert_eq!(framebuffer_hash(chip8.pixels()), 0xe819f5b0);
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::chip8::Instruction;
+
+/// Address programs are loaded at
+pub const PROGRAM_START: usize = 0x200;
+
+/// Why an address is labelled, by increasing priority when several apply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    /// loaded into I by Annn or F000 nnnn
+    Data,
+    /// base address of a Bnnn jump table
+    Table,
+    /// target of a 1nnn jump
+    Jump,
+    /// target of a 2nnn call
+    Subroutine,
+}
+
+fn label_name(kind: LabelKind, addr: usize) -> String {
+    match kind {
+        LabelKind::Data => format!("data_{:03X}", addr),
+        LabelKind::Table => format!("table_{:03X}", addr),
+        LabelKind::Jump => format!("L{:03X}", addr),
+        LabelKind::Subroutine => format!("sub_{:03X}", addr),
+    }
+}
+
+/// Code reachable from PROGRAM_START and the labels referenced by it
+struct Analysis {
+    code: BTreeMap<usize, Instruction>,
+    labels: BTreeMap<usize, LabelKind>,
+}
+
+impl Analysis {
+    fn add_label(&mut self, addr: usize, kind: LabelKind) {
+        let label = self.labels.entry(addr).or_insert(kind);
+        *label = (*label).max(kind);
+    }
+}
+
+/// Recursive-descent traversal of the program loaded in `memory[PROGRAM_START..end]`,
+/// following jumps, calls, both outcomes of skips and Bnnn jump tables
+fn analyze(memory: &[u8], end: usize) -> Analysis {
+    let mut analysis = Analysis { code: BTreeMap::new(), labels: BTreeMap::new() };
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(mut addr) = pending.pop() {
+        while addr >= PROGRAM_START && !analysis.code.contains_key(&addr) {
+            let instr = Instruction::read(memory, addr);
+            if addr + instr.size() > end {
+                break;
+            }
+            if let Instruction::Unknown(_) = instr {
+                break;
+            }
+            analysis.code.insert(addr, instr);
+            let next = addr + instr.size();
+
+            match instr {
+                Instruction::Jp { nnn } => {
+                    analysis.add_label(nnn as usize, LabelKind::Jump);
+                    pending.push(nnn as usize);
+                    break;
+                }
+                Instruction::Call { nnn } => {
+                    analysis.add_label(nnn as usize, LabelKind::Subroutine);
+                    pending.push(nnn as usize);
+                }
+                // the table is a run of jumps indexed by V0, assumed to end at the first other instruction
+                Instruction::JpV0 { nnn } => {
+                    analysis.add_label(nnn as usize, LabelKind::Table);
+                    let mut entry = nnn as usize;
+                    while entry + 2 <= end {
+                        match Instruction::read(memory, entry) {
+                            Instruction::Jp { .. } => pending.push(entry),
+                            _ => break,
+                        }
+                        entry += 2;
+                    }
+                    break;
+                }
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::LdI { nnn } => analysis.add_label(nnn as usize, LabelKind::Data),
+                Instruction::LdILong { nnnn } => analysis.add_label(nnnn as usize, LabelKind::Data),
+                _ if instr.is_skip() => pending.push(next + Instruction::read(memory, next).size()),
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+    analysis
+}
+
+/// One line of the listing after its label
+enum Item {
+    Code(Instruction),
+    /// bytes up to the end address
+    Data(usize),
+}
+
+/// Disassemble a ROM into an assembly listing in Cowgod syntax.
+///
+/// Code is found by recursive descent from PROGRAM_START, the remaining bytes are emitted
+/// as `db` tables. Jump targets, subroutines, jump tables and addresses loaded into I are
+/// labelled. Data loaded into I is emitted one byte per line, with a sprite preview.
+pub fn disassemble_rom(rom: &[u8]) -> String {
+    let end = PROGRAM_START + rom.len();
+    let mut memory = vec![0; PROGRAM_START];
+    memory.extend_from_slice(rom);
+    let analysis = analyze(&memory, end);
+
+    // linear sweep, data runs end at the next instruction or label
+    let mut items = BTreeMap::new();
+    let mut addr = PROGRAM_START;
+    while addr < end {
+        if let Some(instr) = analysis.code.get(&addr) {
+            items.insert(addr, Item::Code(*instr));
+            addr += instr.size();
+        } else {
+            let start = addr;
+            addr += 1;
+            while addr < end && !analysis.code.contains_key(&addr) && !analysis.labels.contains_key(&addr) {
+                addr += 1;
+            }
+            items.insert(start, Item::Data(addr));
+        }
+    }
+
+    // labels inside an instruction or outside of the program stay numeric
+    let labels: BTreeMap<usize, LabelKind> = analysis.labels.iter()
+        .filter(|(addr, _)| items.contains_key(addr))
+        .map(|(addr, kind)| (*addr, *kind))
+        .collect();
+    let operand = |addr: u16, digits: usize| match labels.get(&(addr as usize)) {
+        Some(kind) => label_name(*kind, addr as usize),
+        None => format!("#{:0width$X}", addr, width = digits),
+    };
+    let sprites: BTreeSet<usize> = labels.iter()
+        .filter(|(_, kind)| **kind == LabelKind::Data)
+        .map(|(addr, _)| *addr)
+        .collect();
+
+    let mut listing = format!("; {} bytes loaded at #{:03X}\n", rom.len(), PROGRAM_START);
+    for (addr, item) in &items {
+        if let Some(kind) = labels.get(addr) {
+            listing.push_str(&format!("\n{}:\n", label_name(*kind, *addr)));
+        }
+
+        match item {
+            Item::Code(instr) => {
+                let text = match *instr {
+                    Instruction::Jp { nnn } => format!("JP {}", operand(nnn, 3)),
+                    Instruction::Call { nnn } => format!("CALL {}", operand(nnn, 3)),
+                    Instruction::JpV0 { nnn } => format!("JP V0, {}", operand(nnn, 3)),
+                    Instruction::LdI { nnn } => format!("LD I, {}", operand(nnn, 3)),
+                    Instruction::LdILong { nnnn } => format!("LD I, LONG {}", operand(nnnn, 4)),
+                    _ => instr.to_string(),
+                };
+                let words = match instr.encode() {
+                    (word, Some(next)) => format!("{:04X} {:04X}", word, next),
+                    (word, None) => format!("{:04X}", word),
+                };
+                listing.push_str(&format!("    {:<44}; {:03X}: {}\n", text, addr, words));
+            }
+            Item::Data(data_end) if sprites.contains(addr) => {
+                for (offset, byte) in memory[*addr..*data_end].iter().enumerate() {
+                    let preview: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+                    listing.push_str(&format!("    {:<44}; {:03X}: {}\n", format!("db #{:02X}", byte), addr + offset, preview));
+                }
+            }
+            Item::Data(data_end) => {
+                for (line, bytes) in memory[*addr..*data_end].chunks(8).enumerate() {
+                    let bytes: Vec<String> = bytes.iter().map(|byte| format!("#{:02X}", byte)).collect();
+                    listing.push_str(&format!("    {:<44}; {:03X}\n", format!("db {}", bytes.join(", ")), addr + 8 * line));
+                }
+            }
+        }
+    }
+    listing
+}
+
+#[test]
+fn disassemble_rom_follows_control_flow() {
+    let rom = [
+        0x22, 0x0A, // 200: CALL sub_20A
+        0x30, 0x01, // 202: SE V0, #01
+        0x12, 0x08, // 204: JP L208
+        0xB2, 0x10, // 206: JP V0, table_210
+        0x12, 0x08, // 208: JP L208
+        0xA2, 0x14, // 20A: LD I, data_214
+        0xD0, 0x11, // 20C: DRW V0, V1, 1
+        0x00, 0xEE, // 20E: RET
+        0x12, 0x00, // 210: JP #200
+        0x12, 0x04, // 212: JP L204
+        0xF0, 0x90, // 214: sprite
+    ];
+    let listing = disassemble_rom(&rom);
+    let expected = "; 22 bytes loaded at #200
+
+L200:
+    CALL sub_20A                                ; 200: 220A
+    SE V0, #01                                  ; 202: 3001
+
+L204:
+    JP L208                                     ; 204: 1208
+    JP V0, table_210                            ; 206: B210
+
+L208:
+    JP L208                                     ; 208: 1208
+
+sub_20A:
+    LD I, data_214                              ; 20A: A214
+    DRW V0, V1, 1                               ; 20C: D011
+    RET                                         ; 20E: 00EE
+
+table_210:
+    JP L200                                     ; 210: 1200
+    JP L204                                     ; 212: 1204
+
+data_214:
+    db #F0                                      ; 214: ####....
+    db #90                                      ; 215: #..#....
+";
+    assert_eq!(listing, expected);
+}
+
+#[test]
+fn disassemble_rom_emits_unreachable_bytes_as_data() {
+    // JP L200, then bytes never executed
+    let listing = disassemble_rom(&[0x12, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+    assert!(listing.ends_with("    db #00, #01, #02, #03, #04, #05, #06, #07   ; 202\n    db #08                                      ; 20A\n"));
+}
@@ -0,0 +1,469 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::chip8::state::crc32;
+use crate::chip8::{Backend, Chip8, MemoryIncrement, Quirks, RngKind, Timing};
+
+const MOVIE_MAGIC: &str = "chip8-movie";
+const MOVIE_VERSION: u32 = 1;
+
+/// Frames between state hashes when recording, 0 disables them
+pub const DEFAULT_HASH_INTERVAL: u32 = 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    /// malformed movie file, `line` starts at 1
+    Parse { line: usize, message: String },
+    /// the movie was recorded with another ROM
+    RomMismatch { expected: u32, actual: u32 },
+    Io(io::Error),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "Invalid movie, line {}: {}", line, message),
+            MovieError::RomMismatch { expected, actual } =>
+                write!(f, "Movie recorded with another ROM (expected CRC-32 {:08x}, got {:08x})", expected, actual),
+            MovieError::Io(err) => write!(f, "Movie I/O error: {}", err),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+/// Machine configuration a movie was recorded with, replays start from the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieHeader {
+    /// CRC-32 of the ROM
+    pub rom_hash: u32,
+    pub seed: u64,
+    pub rng: RngKind,
+    pub quirks: Quirks,
+    /// instructions per second
+    pub cpu_frequency: u32,
+    pub timing: Timing,
+    /// frames between state hashes, 0 when the movie has none
+    pub hash_interval: u32,
+}
+
+/// State hashes disagree between the recording and the replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Movie desync at frame {}: state hash {:08x}, expected {:08x}", self.frame, self.actual, self.expected)
+    }
+}
+
+/// Keypad state of every frame since power-on, replayed bit-exactly by `Chip8::play_movie()`.
+///
+/// Stored as text: a header, then `keys <bitmask> <frames>` lines holding the keys (bit n for key n)
+/// for a number of consecutive frames, and `hash <frame> <crc>` lines holding the CRC-32
+/// of the save state after a frame:
+/// ```text
+/// chip8-movie 1
+/// rom e819f5b0
+/// seed 200
+/// rng xorshift
+/// quirks shift_uses_vy=0 memory_increment=none jump_uses_vx=0 vf_reset=0 clip_sprites=0 display_wait=0
+/// cpu-frequency 480
+/// timing fixed
+/// hash-interval 60
+/// keys 0000 30
+/// keys 0020 30
+/// hash 59 1c3e0a4f
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    header: MovieHeader,
+    /// (first frame, keys held) of each run of frames holding the same keys, sorted by frame
+    keys: Vec<(u32, u16)>,
+    /// number of frames
+    len: u32,
+    /// (frame, state hash after the frame) sorted by frame
+    hashes: Vec<(u32, u32)>,
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Movie {
+        Movie { header, keys: Vec::new(), len: 0, hashes: Vec::new() }
+    }
+
+    /// Read a movie written by `save()`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, MovieError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn header(&self) -> &MovieHeader {
+        &self.header
+    }
+
+    /// Number of frames recorded
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keys held during `frame`, bit n set for key n
+    pub fn keys(&self, frame: u32) -> Option<u16> {
+        if frame >= self.len {
+            return None;
+        }
+        let run = self.keys.partition_point(|(start, _)| *start <= frame) - 1;
+        Some(self.keys[run].1)
+    }
+
+    /// State hash recorded after `frame`, if any
+    pub fn hash(&self, frame: u32) -> Option<u32> {
+        self.hashes.binary_search_by_key(&frame, |(f, _)| *f).ok().map(|index| self.hashes[index].1)
+    }
+
+    /// Append a frame, ignored once the movie holds `u32::MAX` frames
+    pub fn push(&mut self, keys: u16, hash: Option<u32>) {
+        let frame = self.len;
+        if self.extend(keys, 1).is_some() {
+            if let Some(hash) = hash {
+                self.hashes.push((frame, hash));
+            }
+        }
+    }
+
+    /// Append `frames` frames holding `keys`, None if the movie would exceed `u32::MAX` frames
+    fn extend(&mut self, keys: u16, frames: u32) -> Option<()> {
+        let len = self.len.checked_add(frames)?;
+        if frames > 0 && self.keys.last().is_none_or(|(_, last)| *last != keys) {
+            self.keys.push((self.len, keys));
+        }
+        self.len = len;
+        Some(())
+    }
+
+    /// Fail if `rom` is not the ROM the movie was recorded with
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let actual = crc32(rom);
+        if actual != self.header.rom_hash {
+            return Err(MovieError::RomMismatch { expected: self.header.rom_hash, actual });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        let quirks = &header.quirks;
+        writeln!(f, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(f, "rom {:08x}", header.rom_hash)?;
+        writeln!(f, "seed {}", header.seed)?;
+        writeln!(f, "rng {}", match header.rng {
+            RngKind::XorShift => "xorshift",
+            RngKind::Table => "table",
+        })?;
+        writeln!(f, "quirks shift_uses_vy={} memory_increment={} jump_uses_vx={} vf_reset={} clip_sprites={} display_wait={}",
+                 quirks.shift_uses_vy as u8,
+                 match quirks.memory_increment {
+                     MemoryIncrement::None => "none",
+                     MemoryIncrement::X => "x",
+                     MemoryIncrement::XPlusOne => "x+1",
+                 },
+                 quirks.jump_uses_vx as u8, quirks.vf_reset as u8, quirks.clip_sprites as u8, quirks.display_wait as u8)?;
+        writeln!(f, "cpu-frequency {}", header.cpu_frequency)?;
+        writeln!(f, "timing {}", match header.timing {
+            Timing::Fixed => "fixed",
+            Timing::CosmacVip => "vip",
+        })?;
+        writeln!(f, "hash-interval {}", header.hash_interval)?;
+
+        // runs of identical keys are split after hashed frames
+        let mut hashes = self.hashes.iter().peekable();
+        for (run, (start, keys)) in self.keys.iter().enumerate() {
+            let end = self.keys.get(run + 1).map_or(self.len, |(next, _)| *next);
+            let mut start = *start;
+            while let Some((frame, hash)) = hashes.next_if(|(frame, _)| *frame < end) {
+                writeln!(f, "keys {:04x} {}", keys, frame + 1 - start)?;
+                writeln!(f, "hash {} {:08x}", frame, hash)?;
+                start = frame + 1;
+            }
+            if start < end {
+                writeln!(f, "keys {:04x} {}", keys, end - start)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Movie {
+    type Err = MovieError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate()
+            .map(|(index, line)| (index + 1, line.split_whitespace().collect::<Vec<&str>>()))
+            .filter(|(_, words)| !words.is_empty());
+        let mut field = |name: &str| -> Result<(usize, Vec<&str>), MovieError> {
+            match lines.next() {
+                Some((line, words)) if words[0] == name => Ok((line, words[1..].to_vec())),
+                Some((line, _)) => Err(MovieError::Parse { line, message: format!("expected {}", name) }),
+                None => Err(MovieError::Parse { line: s.lines().count() + 1, message: format!("expected {}", name) }),
+            }
+        };
+
+        let (line, version) = field(MOVIE_MAGIC)?;
+        if version != [MOVIE_VERSION.to_string()] {
+            return Err(MovieError::Parse { line, message: format!("unsupported version {}", version.join(" ")) });
+        }
+        let (line, rom) = field("rom")?;
+        let rom_hash = parse_word(line, &rom, |word| u32::from_str_radix(word, 16).ok())?;
+        let (line, seed) = field("seed")?;
+        let seed = parse_word(line, &seed, |word| word.parse().ok())?;
+        let (line, rng) = field("rng")?;
+        let rng = parse_word(line, &rng, |word| word.parse().ok())?;
+        let (line, quirks) = field("quirks")?;
+        let quirks = parse_quirks(&quirks).map_err(|message| MovieError::Parse { line, message })?;
+        let (line, frequency) = field("cpu-frequency")?;
+        let cpu_frequency = parse_word(line, &frequency, |word| word.parse().ok())?;
+        let (line, timing) = field("timing")?;
+        let timing = parse_word(line, &timing, |word| word.parse().ok())?;
+        let (line, interval) = field("hash-interval")?;
+        let hash_interval = parse_word(line, &interval, |word| word.parse().ok())?;
+
+        let mut movie = Movie::new(MovieHeader { rom_hash, seed, rng, quirks, cpu_frequency, timing, hash_interval });
+        for (line, words) in lines {
+            let invalid = |message: &str| MovieError::Parse { line, message: message.to_string() };
+            match (words[0], &words[1..]) {
+                ("keys", [keys, frames]) => {
+                    let keys = u16::from_str_radix(keys, 16).map_err(|_| invalid("invalid key bitmask"))?;
+                    let frames = frames.parse::<u32>().map_err(|_| invalid("invalid frame count"))?;
+                    movie.extend(keys, frames).ok_or_else(|| invalid("too many frames"))?;
+                }
+                ("hash", [frame, hash]) => {
+                    let frame = frame.parse::<u32>().map_err(|_| invalid("invalid frame"))?;
+                    let hash = u32::from_str_radix(hash, 16).map_err(|_| invalid("invalid hash"))?;
+                    if frame >= movie.len() || movie.hashes.last().is_some_and(|(last, _)| *last >= frame) {
+                        return Err(invalid("hash frame out of order"));
+                    }
+                    movie.hashes.push((frame, hash));
+                }
+                _ => return Err(invalid("expected keys <bitmask> <frames> or hash <frame> <crc>")),
+            }
+        }
+        Ok(movie)
+    }
+}
+
+/// Parse the single value of a header line
+fn parse_word<T>(line: usize, words: &[&str], parse: impl Fn(&str) -> Option<T>) -> Result<T, MovieError> {
+    match words {
+        [word] => parse(word).ok_or_else(|| MovieError::Parse { line, message: format!("invalid value {}", word) }),
+        _ => Err(MovieError::Parse { line, message: "expected a single value".to_string() }),
+    }
+}
+
+fn parse_quirks(words: &[&str]) -> Result<Quirks, String> {
+    let mut quirks = Quirks::default();
+    let mut seen = Vec::new();
+    for word in words {
+        let invalid = || format!("invalid quirk {}", word);
+        let (name, value) = word.split_at(word.find('=').ok_or_else(invalid)?);
+        let value = &value[1..];
+        let flag = || match value {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(invalid()),
+        };
+        match name {
+            "shift_uses_vy" => quirks.shift_uses_vy = flag()?,
+            "memory_increment" => quirks.memory_increment = match value {
+                "none" => MemoryIncrement::None,
+                "x" => MemoryIncrement::X,
+                "x+1" => MemoryIncrement::XPlusOne,
+                _ => return Err(invalid()),
+            },
+            "jump_uses_vx" => quirks.jump_uses_vx = flag()?,
+            "vf_reset" => quirks.vf_reset = flag()?,
+            "clip_sprites" => quirks.clip_sprites = flag()?,
+            "display_wait" => quirks.display_wait = flag()?,
+            _ => return Err(invalid()),
+        }
+        seen.push(name);
+    }
+    seen.sort_unstable();
+    seen.dedup();
+    if seen.len() != 6 {
+        return Err("expected the 6 quirks once each".to_string());
+    }
+    Ok(quirks)
+}
+
+/// Movie being recorded or played back by a `Chip8`
+pub struct ActiveMovie {
+    movie: Movie,
+    recording: bool,
+    /// frames run since the movie started
+    frame: u32,
+    /// first state hash mismatch during playback
+    desync: Option<Desync>,
+}
+
+impl<B: Backend> Chip8<B> {
+    /// Start recording the keypad of every frame. Must be called right after `load_rom()`:
+    /// the random number generator is reseeded so that the movie can be replayed from power-on.
+    /// The CRC-32 of the save state is stored every `hash_interval` frames, 0 disables it.
+    pub fn start_recording(&mut self, rom: &[u8], seed: u64, rng: RngKind, hash_interval: u32) {
+        self.set_rng(rng.create(Some(seed)));
+        let header = MovieHeader {
+            rom_hash: crc32(rom),
+            seed,
+            rng,
+            quirks: self.quirks,
+            cpu_frequency: self.cpu_frequency,
+            timing: self.timing,
+            hash_interval,
+        };
+        self.movie = Some(ActiveMovie { movie: Movie::new(header), recording: true, frame: 0, desync: None });
+    }
+
+    /// Replay `movie` in place of the user's input. Must be called right after `load_rom()` with the ROM
+    /// the movie was recorded with, the quirks, CPU frequency, timing and random number generator are taken from the movie.
+    /// Once the movie ends, input comes from the backend again.
+    pub fn play_movie(&mut self, movie: Movie, rom: &[u8]) -> Result<(), MovieError> {
+        movie.check_rom(rom)?;
+        let header = movie.header;
+        self.set_quirks(header.quirks);
+        self.set_cpu_frequency(header.cpu_frequency);
+        self.set_timing(header.timing);
+        self.set_rng(header.rng.create(Some(header.seed)));
+        self.movie = Some(ActiveMovie { movie, recording: false, frame: 0, desync: None });
+        Ok(())
+    }
+
+    /// Stop recording or playing, returns the movie
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(|active| active.movie)
+    }
+
+    /// Frames run since the movie started, None without a movie
+    pub fn movie_frame(&self) -> Option<u32> {
+        self.movie.as_ref().map(|active| active.frame)
+    }
+
+    /// True once a movie being played back has no frames left
+    pub fn movie_finished(&self) -> bool {
+        self.movie.as_ref().is_some_and(|active| !active.recording && active.frame >= active.movie.len())
+    }
+
+    /// First desync detected while playing back a movie
+    pub fn movie_desync(&self) -> Option<Desync> {
+        self.movie.as_ref().and_then(|active| active.desync)
+    }
+
+    pub fn movie_active(&self) -> bool {
+        self.movie.is_some()
+    }
+}
+
+impl ActiveMovie {
+    /// Before a frame: hold the keys of the movie when playing it back
+    pub fn begin_frame(&self, keypad: &mut [bool]) {
+        if let Some(keys) = self.movie.keys(self.frame).filter(|_| !self.recording) {
+            for (key, pressed) in keypad.iter_mut().enumerate() {
+                *pressed = keys & (1 << key) != 0;
+            }
+        }
+    }
+
+    /// True if the state hash is recorded or checked after the current frame
+    pub fn needs_hash(&self) -> bool {
+        let interval = self.movie.header.hash_interval;
+        if self.recording {
+            interval > 0 && (self.frame + 1).is_multiple_of(interval)
+        } else {
+            self.movie.hash(self.frame).is_some()
+        }
+    }
+
+    /// After a complete frame: record the keys and state hash, or check the state hash
+    pub fn end_frame(&mut self, keypad: &[bool], hash: Option<u32>) {
+        if self.recording {
+            let keys = keypad.iter().enumerate().fold(0u16, |keys, (key, pressed)| keys | ((*pressed as u16) << key));
+            self.movie.push(keys, hash);
+        } else if let (Some(expected), Some(actual)) = (self.movie.hash(self.frame), hash) {
+            if expected != actual && self.desync.is_none() {
+                self.desync = Some(Desync { frame: self.frame, expected, actual });
+            }
+        }
+        self.frame += 1;
+    }
+}
+
+#[test]
+fn movie_text_round_trip() {
+    let header = MovieHeader {
+        rom_hash: 0xe819f5b0,
+        seed: 200,
+        rng: RngKind::Table,
+        quirks: Quirks::from(crate::chip8::QuirksPreset::CosmacVip),
+        cpu_frequency: 1000,
+        timing: Timing::CosmacVip,
+        hash_interval: 2,
+    };
+    let mut movie = Movie::new(header);
+    for (keys, hash) in [(0, None), (0, Some(0x1234)), (0, None), (0x20, None), (0x20, Some(0xABCD))].iter() {
+        movie.push(*keys, *hash);
+    }
+    let text = movie.to_string();
+    assert!(text.ends_with("cpu-frequency 1000\ntiming vip\nhash-interval 2\nkeys 0000 2\nhash 1 00001234\nkeys 0000 1\nkeys 0020 2\nhash 4 0000abcd\n"));
+    assert_eq!(text.parse::<Movie>().unwrap(), movie);
+    assert_eq!(movie.keys(3), Some(0x20));
+    assert_eq!(movie.hash(4), Some(0xABCD));
+    assert_eq!(movie.hash(3), None);
+
+    match text.replace("keys 0020 2", "keys 0020").parse::<Movie>() {
+        Err(MovieError::Parse { line: 12, .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(text.replace("vf_reset=1 ", "").parse::<Movie>().is_err());
+    match text.replace("keys 0020 2", "keys 0020 18446744073709551615").parse::<Movie>() {
+        Err(MovieError::Parse { line: 12, .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    match text.replace("keys 0020 2", "keys 0020 4294967295").parse::<Movie>() {
+        Err(MovieError::Parse { line: 12, message }) => assert_eq!(message, "too many frames"),
+        other => panic!("unexpected {:?}", other),
+    }
+    // long runs are not expanded frame by frame
+    let long = text.replace("keys 0020 2\nhash 4 0000abcd\n", "keys 0020 4000000000\nkeys 0001 1\n").parse::<Movie>().unwrap();
+    assert_eq!(long.len(), 4_000_000_004);
+    assert_eq!(long.keys(3_999_999_999), Some(0x20));
+    assert_eq!(long.keys(4_000_000_003), Some(0x01));
+    assert_eq!(long.keys(4_000_000_004), None);
+    assert_eq!(long.to_string().parse::<Movie>().unwrap(), long);
+    assert!(text.replace("chip8-movie 1", "chip8-movie 2").parse::<Movie>().is_err());
+    match movie.check_rom(b"other") {
+        Err(MovieError::RomMismatch { expected: 0xe819f5b0, .. }) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+}
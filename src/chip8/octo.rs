@@ -0,0 +1,829 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::chip8::asm::{parse_number, register};
+use crate::chip8::{AsmError, Instruction, MEM_SIZE, PROGRAM_START};
+
+/// Macro expansions nested deeper than this are rejected, which also catches recursive macros
+const MAX_MACRO_DEPTH: usize = 64;
+
+/// Whitespace-separated word of the source
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    /// macro expansion depth
+    depth: usize,
+}
+
+/// Split the source into words, `#` starts a comment until the end of the line
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (column, c) in line.chars().chain(Some(' ')).enumerate() {
+            if c == '#' || c.is_whitespace() {
+                if let Some(start) = start.take() {
+                    let text = line.chars().skip(start).take(column - start).collect();
+                    tokens.push(Token { text, line: index + 1, column: start + 1, depth: 0 });
+                }
+                if c == '#' {
+                    break;
+                }
+            } else if start.is_none() {
+                start = Some(column);
+            }
+        }
+    }
+    tokens
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Open control-flow block, holding the addresses of jumps to patch
+enum Flow {
+    Begin { jump: usize, token: Token },
+    Else { jump: usize, token: Token },
+    Loop { start: usize, breaks: Vec<usize>, token: Token },
+}
+
+/// Address operand of the instruction at `addr`, resolved once all labels are known
+struct Fixup {
+    addr: usize,
+    token: Token,
+    long: bool,
+}
+
+/// Single-pass compiler, forward references to labels are patched at the end
+struct Compiler {
+    file: String,
+    /// remaining tokens, in reverse order
+    tokens: Vec<Token>,
+    memory: Vec<u8>,
+    here: usize,
+    end: usize,
+    /// labels, constants and calculated values
+    values: HashMap<String, i64>,
+    /// names of `values` defined by `: name`, called when used as a statement
+    labels: HashSet<String>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    /// PROGRAM_START holds a jump to main, dropped when main is defined right after it
+    main_jump: bool,
+    last: Token,
+}
+
+impl Compiler {
+    fn error(&self, token: &Token, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: token.line, column: token.column, message }
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            }
+            None => Err(self.error(&self.last, format!("Unexpected end of file after {}", self.last.text))),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.last().is_some_and(|token| token.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, format!("Expected {}, found {}", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.as_register(&token).ok_or_else(|| self.error(&token, format!("Expected a register, found {}", token.text)))
+    }
+
+    fn as_register(&self, token: &Token) -> Option<u8> {
+        register(&token.text).or_else(|| self.aliases.get(&token.text).copied())
+    }
+
+    fn is_name(text: &str) -> bool {
+        text.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    fn define(&mut self, token: &Token, value: i64) -> Result<(), AsmError> {
+        if !Compiler::is_name(&token.text) || self.as_register(token).is_some() {
+            return Err(self.error(token, format!("Invalid name {}", token.text)));
+        }
+        if self.values.insert(token.text.clone(), value).is_some() {
+            return Err(self.error(token, format!("{} is already defined", token.text)));
+        }
+        Ok(())
+    }
+
+    /// Number, defined name or `{ expression }`
+    fn value(&mut self) -> Result<(i64, Token), AsmError> {
+        let token = self.next()?;
+        if token.text == "{" {
+            return Ok((self.calc("}")?.floor() as i64, token));
+        }
+        match parse_number(&token.text).or_else(|| self.values.get(&token.text).copied()) {
+            Some(value) => Ok((value, token)),
+            None => Err(self.error(&token, format!("Undefined name {}", token.text))),
+        }
+    }
+
+    fn value_max(&mut self, min: i64, max: i64) -> Result<i64, AsmError> {
+        let (value, token) = self.value()?;
+        if value < min || value > max {
+            return Err(self.error(&token, format!("Value {} out of range ({} to {})", value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        Ok(self.value_max(-128, 0xFF)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        Ok(self.value_max(0, 0xF)? as u8)
+    }
+
+    /// Address operand of the instruction emitted next, labels may be defined later
+    fn address(&mut self, long: bool) -> Result<u16, AsmError> {
+        let max = if long { 0xFFFF } else { 0xFFF };
+        let known = match self.tokens.last() {
+            Some(token) => token.text == "{" || parse_number(&token.text).is_some() || self.values.contains_key(&token.text),
+            None => true,
+        };
+        if known {
+            return Ok(self.value_max(0, max)? as u16);
+        }
+        let token = self.next()?;
+        if !Compiler::is_name(&token.text) {
+            return Err(self.error(&token, format!("Expected an address, found {}", token.text)));
+        }
+        self.fixups.push(Fixup { addr: self.here, token, long });
+        Ok(0)
+    }
+
+    /// Expression up to `close`, evaluated right to left without precedence like Octo
+    fn calc(&mut self, close: &str) -> Result<f64, AsmError> {
+        let a = self.calc_term()?;
+        let op = self.next()?;
+        if op.text == close {
+            return Ok(a);
+        }
+        let b = self.calc(close)?;
+        let truth = |b: bool| if b { 1.0 } else { 0.0 };
+        let result = match op.text.as_str() {
+            "+" => a + b,
+            "-" => a - b,
+            "*" => a * b,
+            "/" => a / b,
+            "%" => a % b,
+            "&" => ((a as i64) & (b as i64)) as f64,
+            "|" => ((a as i64) | (b as i64)) as f64,
+            "^" => ((a as i64) ^ (b as i64)) as f64,
+            "<<" | ">>" => {
+                let shifted = u32::try_from(b as i64).ok().and_then(|b| match op.text.as_str() {
+                    "<<" => (a as i64).checked_shl(b),
+                    _ => (a as i64).checked_shr(b),
+                });
+                shifted.ok_or_else(|| self.error(&op, format!("Shift by {} out of range (0 to 63)", b)))? as f64
+            }
+            "pow" => a.powf(b),
+            "min" => a.min(b),
+            "max" => a.max(b),
+            "<" => truth(a < b),
+            ">" => truth(a > b),
+            "<=" => truth(a <= b),
+            ">=" => truth(a >= b),
+            "==" => truth(a == b),
+            "!=" => truth(a != b),
+            _ => return Err(self.error(&op, format!("Unknown operator {}", op.text))),
+        };
+        Ok(result)
+    }
+
+    fn calc_term(&mut self) -> Result<f64, AsmError> {
+        let token = self.next()?;
+        let value = match token.text.as_str() {
+            "(" => self.calc(")")?,
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => if self.calc_term()? == 0.0 { 1.0 } else { 0.0 },
+            "@" => {
+                let addr = self.calc_term()? as usize;
+                match self.memory.get(addr) {
+                    Some(byte) => *byte as f64,
+                    None => return Err(self.error(&token, format!("Address {} out of memory", addr))),
+                }
+            }
+            "HERE" => self.here as f64,
+            text => match parse_number(text).or_else(|| self.values.get(text).copied()) {
+                Some(value) => value as f64,
+                None => text.parse::<f64>().map_err(|_| self.error(&token, format!("Undefined name {}", text)))?,
+            },
+        };
+        Ok(value)
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AsmError> {
+        if self.here >= MEM_SIZE {
+            return Err(self.error(token, "Program too large".to_string()));
+        }
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, token: &Token, instr: Instruction) -> Result<(), AsmError> {
+        let (word, next) = instr.encode();
+        for word in Some(word).iter().chain(next.iter()) {
+            self.emit_byte(token, (word >> 8) as u8)?;
+            self.emit_byte(token, *word as u8)?;
+        }
+        Ok(())
+    }
+
+    /// Jump with its target patched later, returns its address
+    fn emit_jump(&mut self, token: &Token) -> Result<usize, AsmError> {
+        let addr = self.here;
+        self.emit(token, Instruction::Jp { nnn: 0 })?;
+        Ok(addr)
+    }
+
+    /// Point the jump at `addr` to `target`, `token` being the statement the target comes from
+    fn patch_jump(&mut self, token: &Token, addr: usize, target: usize) -> Result<(), AsmError> {
+        let nnn = self.jump_target(token, target)?;
+        self.memory[addr] = 0x10 | (nnn >> 8) as u8;
+        self.memory[addr + 1] = nnn as u8;
+        Ok(())
+    }
+
+    /// Fail if a jump cannot reach `target`
+    fn jump_target(&self, token: &Token, target: usize) -> Result<u16, AsmError> {
+        if target > 0xFFF {
+            return Err(self.error(token, format!("Jump target {} out of range (0 to {})", target, 0xFFF)));
+        }
+        Ok(target as u16)
+    }
+
+    /// Instructions computing the condition and the skips taken when it is false and when it is true
+    fn condition(&mut self) -> Result<(Vec<Instruction>, Instruction, Instruction), AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let rhs_register = self.tokens.last().and_then(|token| self.as_register(token));
+        if op.text == "key" || op.text == "-key" {
+            let (pressed, released) = (Instruction::Skp { x }, Instruction::Sknp { x });
+            return Ok(if op.text == "key" { (vec![], released, pressed) } else { (vec![], pressed, released) });
+        }
+
+        let (equal, not_equal) = match rhs_register {
+            Some(y) => {
+                self.next()?;
+                (Instruction::SeReg { x, y }, Instruction::SneReg { x, y })
+            }
+            None if op.text == "==" || op.text == "!=" => {
+                let kk = self.byte()?;
+                (Instruction::SeImm { x, kk }, Instruction::SneImm { x, kk })
+            }
+            None => (Instruction::Cls, Instruction::Cls),
+        };
+        match op.text.as_str() {
+            "==" => return Ok((vec![], not_equal, equal)),
+            "!=" => return Ok((vec![], equal, not_equal)),
+            "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(self.error(&op, format!("Unknown comparison {}", op.text))),
+        }
+
+        // vf := rhs, then vf =- x leaves the x >= rhs flag in vf, vf -= x leaves rhs >= x
+        let load = match rhs_register {
+            Some(y) => Instruction::LdReg { x: 0xF, y },
+            None => Instruction::LdImm { x: 0xF, kk: self.byte()? },
+        };
+        let (compare, flag) = match op.text.as_str() {
+            "<" => (Instruction::Subn { x: 0xF, y: x }, 0),
+            ">=" => (Instruction::Subn { x: 0xF, y: x }, 1),
+            ">" => (Instruction::Sub { x: 0xF, y: x }, 0),
+            _ => (Instruction::Sub { x: 0xF, y: x }, 1),
+        };
+        Ok((vec![load, compare], Instruction::SneImm { x: 0xF, kk: flag }, Instruction::SeImm { x: 0xF, kk: flag }))
+    }
+
+    /// Emit the condition followed by a jump taken when it is false, returns the jump address
+    fn emit_condition_jump(&mut self, token: &Token) -> Result<usize, AsmError> {
+        let (prefix, _, skip_if_true) = self.condition()?;
+        for instr in prefix {
+            self.emit(token, instr)?;
+        }
+        self.emit(token, skip_if_true)?;
+        self.emit_jump(token)
+    }
+
+    fn register_statement(&mut self, token: &Token, x: u8) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let y = self.tokens.last().and_then(|token| self.as_register(token));
+        if y.is_some() {
+            self.next()?;
+        }
+        let instr = match (op.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdReg { x, y },
+            (":=", None) if self.peek_is("random") => {
+                self.next()?;
+                Instruction::Rnd { x, kk: self.byte()? }
+            }
+            (":=", None) if self.peek_is("key") => {
+                self.next()?;
+                Instruction::LdVxK { x }
+            }
+            (":=", None) if self.peek_is("delay") => {
+                self.next()?;
+                Instruction::LdVxDt { x }
+            }
+            (":=", None) => Instruction::LdImm { x, kk: self.byte()? },
+            ("+=", Some(y)) => Instruction::AddReg { x, y },
+            ("+=", None) => Instruction::AddImm { x, kk: self.byte()? },
+            ("-=", Some(y)) => Instruction::Sub { x, y },
+            ("-=", None) => Instruction::AddImm { x, kk: self.byte()?.wrapping_neg() },
+            ("=-", Some(y)) => Instruction::Subn { x, y },
+            ("|=", Some(y)) => Instruction::Or { x, y },
+            ("&=", Some(y)) => Instruction::And { x, y },
+            ("^=", Some(y)) => Instruction::Xor { x, y },
+            (">>=", Some(y)) => Instruction::Shr { x, y },
+            ("<<=", Some(y)) => Instruction::Shl { x, y },
+            _ => return Err(self.error(&op, format!("Invalid operation {} on {}", op.text, token.text))),
+        };
+        self.emit(token, instr)
+    }
+
+    fn i_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let instr = match op.text.as_str() {
+            "+=" => Instruction::AddI { x: self.register()? },
+            ":=" if self.peek_is("hex") => {
+                self.next()?;
+                Instruction::LdF { x: self.register()? }
+            }
+            ":=" if self.peek_is("bighex") => {
+                self.next()?;
+                Instruction::LdHf { x: self.register()? }
+            }
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                Instruction::LdILong { nnnn: self.address(true)? }
+            }
+            ":=" => Instruction::LdI { nnn: self.address(false)? },
+            _ => return Err(self.error(&op, format!("Invalid operation {} on i", op.text))),
+        };
+        self.emit(token, instr)
+    }
+
+    /// `save`/`load` with a register or a `vx - vy` range
+    fn range(&mut self, token: &Token, single: fn(u8) -> Instruction, range: fn(u8, u8) -> Instruction) -> Result<(), AsmError> {
+        let x = self.register()?;
+        if self.peek_is("-") {
+            self.next()?;
+            let y = self.register()?;
+            return self.emit(token, range(x, y));
+        }
+        self.emit(token, single(x))
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if let Some(x) = self.as_register(&token) {
+            return self.register_statement(&token, x);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next()?;
+                if name.text == "main" && self.main_jump && self.here == PROGRAM_START + 2 {
+                    self.main_jump = false;
+                    self.here = PROGRAM_START;
+                    self.end = PROGRAM_START;
+                }
+                let here = self.here as i64;
+                self.define(&name, here)?;
+                self.labels.insert(name.text);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let (value, _) = self.value()?;
+                self.define(&name, value)?;
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let value = self.calc("}")?.floor() as i64;
+                self.define(&name, value)?;
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.register()?;
+                if !Compiler::is_name(&name.text) || register(&name.text).is_some() {
+                    return Err(self.error(&name, format!("Invalid name {}", name.text)));
+                }
+                self.aliases.insert(name.text, x);
+            }
+            ":org" => {
+                let addr = self.value_max(PROGRAM_START as i64, MEM_SIZE as i64)?;
+                self.here = addr as usize;
+            }
+            ":macro" => {
+                let name = self.next()?;
+                let mut args = Vec::new();
+                loop {
+                    let arg = self.next()?;
+                    if arg.text == "{" {
+                        break;
+                    }
+                    args.push(arg.text);
+                }
+                let mut body = Vec::new();
+                let mut nesting = 0;
+                loop {
+                    let token = self.next()?;
+                    match token.text.as_str() {
+                        "{" => nesting += 1,
+                        "}" if nesting == 0 => break,
+                        "}" => nesting -= 1,
+                        _ => {}
+                    }
+                    body.push(token);
+                }
+                if !Compiler::is_name(&name.text) {
+                    return Err(self.error(&name, format!("Invalid name {}", name.text)));
+                }
+                self.macros.insert(name.text, Macro { args, body });
+            }
+            ":call" => {
+                let nnn = self.address(false)?;
+                self.emit(&token, Instruction::Call { nnn })?;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(&token, byte)?;
+            }
+            "return" | ";" => self.emit(&token, Instruction::Ret)?,
+            "clear" => self.emit(&token, Instruction::Cls)?,
+            "hires" => self.emit(&token, Instruction::High)?,
+            "lores" => self.emit(&token, Instruction::Low)?,
+            "exit" => self.emit(&token, Instruction::Exit)?,
+            "scroll-left" => self.emit(&token, Instruction::Scl)?,
+            "scroll-right" => self.emit(&token, Instruction::Scr)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Scd { n })?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Scu { n })?;
+            }
+            "audio" => self.emit(&token, Instruction::Audio)?,
+            "plane" => {
+                let n = self.value_max(0, 3)? as u8;
+                self.emit(&token, Instruction::Plane { n })?;
+            }
+            "jump" => {
+                let nnn = self.address(false)?;
+                self.emit(&token, Instruction::Jp { nnn })?;
+            }
+            "jump0" => {
+                let nnn = self.address(false)?;
+                self.emit(&token, Instruction::JpV0 { nnn })?;
+            }
+            "native" => {
+                let nnn = self.address(false)?;
+                self.emit(&token, Instruction::Sys { nnn })?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(&token, Instruction::Drw { x, y, n })?;
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::LdB { x })?;
+            }
+            "save" => self.range(&token, |x| Instruction::Save { x }, |x, y| Instruction::SaveRange { x, y })?,
+            "load" => self.range(&token, |x| Instruction::Load { x }, |x, y| Instruction::LoadRange { x, y })?,
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::SaveFlags { x })?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(&token, Instruction::LoadFlags { x })?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instr = match token.text.as_str() {
+                    "delay" => Instruction::LdDtVx { x },
+                    "buzzer" => Instruction::LdStVx { x },
+                    _ => Instruction::Pitch { x },
+                };
+                self.emit(&token, instr)?;
+            }
+            "i" => self.i_statement(&token)?,
+            "if" => {
+                let (prefix, skip_if_false, skip_if_true) = self.condition()?;
+                let block = self.next()?;
+                let skip = match block.text.as_str() {
+                    "then" => skip_if_false,
+                    "begin" => skip_if_true,
+                    _ => return Err(self.error(&block, format!("Expected then or begin, found {}", block.text))),
+                };
+                for instr in prefix {
+                    self.emit(&token, instr)?;
+                }
+                self.emit(&token, skip)?;
+                if block.text == "begin" {
+                    let jump = self.emit_jump(&token)?;
+                    self.flow.push(Flow::Begin { jump, token });
+                }
+            }
+            "else" => match self.flow.pop() {
+                Some(Flow::Begin { jump, .. }) => {
+                    let end = self.emit_jump(&token)?;
+                    let here = self.here;
+                    self.patch_jump(&token, jump, here)?;
+                    self.flow.push(Flow::Else { jump: end, token });
+                }
+                _ => return Err(self.error(&token, "else without if ... begin".to_string())),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin { jump, .. }) | Some(Flow::Else { jump, .. }) => {
+                    let here = self.here;
+                    self.patch_jump(&token, jump, here)?;
+                }
+                _ => return Err(self.error(&token, "end without if ... begin".to_string())),
+            },
+            "loop" => self.flow.push(Flow::Loop { start: self.here, breaks: Vec::new(), token }),
+            "while" => {
+                let jump = self.emit_condition_jump(&token)?;
+                match self.flow.iter_mut().rev().find_map(|flow| match flow {
+                    Flow::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err(self.error(&token, "while outside of a loop".to_string())),
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, breaks, .. }) => {
+                    let nnn = self.jump_target(&token, start)?;
+                    self.emit(&token, Instruction::Jp { nnn })?;
+                    let here = self.here;
+                    for jump in breaks {
+                        self.patch_jump(&token, jump, here)?;
+                    }
+                }
+                _ => return Err(self.error(&token, "again without loop".to_string())),
+            },
+            text if self.macros.contains_key(text) => self.expand(&token)?,
+            text if !self.labels.contains(text)
+                && (parse_number(text).is_some() || text == "{" || self.values.contains_key(text)) => {
+                self.tokens.push(token.clone());
+                let byte = self.byte()?;
+                self.emit_byte(&token, byte)?;
+            }
+            text if Compiler::is_name(text) => {
+                self.tokens.push(token.clone());
+                let nnn = self.address(false)?;
+                self.emit(&token, Instruction::Call { nnn })?;
+            }
+            text => return Err(self.error(&token, format!("Unexpected {}", text))),
+        }
+        Ok(())
+    }
+
+    /// Push the macro body back on the tokens, with its arguments substituted
+    fn expand(&mut self, token: &Token) -> Result<(), AsmError> {
+        if token.depth >= MAX_MACRO_DEPTH {
+            return Err(self.error(token, format!("Macro {} expanded too deeply", token.text)));
+        }
+        let count = self.macros[&token.text].args.len();
+        let mut args = HashMap::new();
+        for index in 0..count {
+            let arg = self.next()?;
+            args.insert(self.macros[&token.text].args[index].clone(), arg.text);
+        }
+        let body: Vec<Token> = self.macros[&token.text].body.iter().rev()
+            .map(|body_token| Token {
+                text: args.get(&body_token.text).cloned().unwrap_or_else(|| body_token.text.clone()),
+                depth: token.depth + 1,
+                ..body_token.clone()
+            })
+            .collect();
+        self.tokens.extend(body);
+        Ok(())
+    }
+
+    /// Check blocks are closed, patch forward references and return the image loaded at PROGRAM_START
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if let Some(flow) = self.flow.last() {
+            let (token, message) = match flow {
+                Flow::Begin { token, .. } | Flow::Else { token, .. } => (token, "begin without end"),
+                Flow::Loop { token, .. } => (token, "loop without again"),
+            };
+            return Err(self.error(token, message.to_string()));
+        }
+
+        if self.main_jump {
+            let main = Token { text: "main".to_string(), line: 1, column: 1, depth: 0 };
+            self.patch_jump(&main, PROGRAM_START, 0)?;
+            self.fixups.push(Fixup { addr: PROGRAM_START, token: main, long: false });
+        }
+        for fixup in &self.fixups {
+            let value = *self.values.get(&fixup.token.text)
+                .ok_or_else(|| self.error(&fixup.token, format!("Undefined name {}", fixup.token.text)))?;
+            let max = if fixup.long { 0xFFFF } else { 0xFFF };
+            if value < 0 || value > max {
+                return Err(self.error(&fixup.token, format!("Value {} out of range (0 to {})", value, max)));
+            }
+            if fixup.long {
+                self.memory[fixup.addr + 2] = (value >> 8) as u8;
+                self.memory[fixup.addr + 3] = value as u8;
+            } else {
+                self.memory[fixup.addr] |= (value >> 8) as u8;
+                self.memory[fixup.addr + 1] |= value as u8;
+            }
+        }
+        Ok(self.memory[PROGRAM_START..self.end].to_vec())
+    }
+}
+
+/// Compile Octo source into an image loaded at PROGRAM_START, ready for `Chip8::load_rom()`.
+///
+/// Supports labels (`: name`), register assignments (`v0 := 5`, `i := long label`), `if ... then`,
+/// `if ... begin ... else ... end`, `loop ... while ... again`, `:alias`, `:const`, `:calc`, `:macro`,
+/// `:org`, `:call`, `:byte` and raw bytes. Execution starts at `main`.
+pub fn compile_octo(source: &str) -> Result<Vec<u8>, AsmError> {
+    compile_named(source, "<input>")
+}
+
+/// Compile an Octo source file
+pub fn compile_octo_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|err| AsmError {
+        file: path.display().to_string(), line: 0, column: 0, message: err.to_string(),
+    })?;
+    compile_named(&source, &path.display().to_string())
+}
+
+fn compile_named(source: &str, file: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let start = Token { text: String::new(), line: 1, column: 1, depth: 0 };
+    let mut compiler = Compiler {
+        file: file.to_string(),
+        tokens,
+        memory: vec![0; MEM_SIZE],
+        here: PROGRAM_START + 2,
+        end: PROGRAM_START + 2,
+        values: HashMap::new(),
+        labels: HashSet::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        main_jump: true,
+        last: start,
+    };
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+#[test]
+fn compile_octo_program() {
+    let source = "
+:const SPEED 2
+:alias x v1
+:calc HALF { SPEED * 8 / 2 }
+:macro move reg amount { reg += amount }
+
+: main
+    x := HALF
+    move x SPEED     # expands to x += SPEED
+    i := sprite
+    sprite x v2 3
+    draw
+    loop
+        v0 := key
+        if v0 == 5 then jump main
+    again
+
+: draw
+    i := long sprite
+    ;
+
+:org 0x240
+: sprite
+    0xF0 0x90 0xF0
+";
+    let mut expected = vec![
+        0x61, 0x08, 0x71, 0x02, 0xA2, 0x40, 0xD1, 0x23, 0x22, 0x12,
+        0xF0, 0x0A, 0x40, 0x05, 0x12, 0x00, 0x12, 0x0A,
+        0xF0, 0x00, 0x02, 0x40, 0x00, 0xEE,
+    ];
+    expected.resize(0x40, 0);
+    expected.extend_from_slice(&[0xF0, 0x90, 0xF0]);
+    assert_eq!(compile_octo(source), Ok(expected));
+
+    // code before main is jumped over
+    assert_eq!(compile_octo(": sub ; : main sub"), Ok(vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]));
+}
+
+#[test]
+fn compile_octo_control_flow() {
+    use crate::chip8::Chip8;
+
+    let source = "
+: main
+    # v1 = 1 + 2 + ... + 10
+    v0 := 1
+    loop
+        while v0 <= 10
+        v1 += v0
+        v0 += 1
+    again
+
+    if v1 > 54 begin
+        v2 := 1
+    else
+        v2 := 2
+    end
+    if v1 < 55 begin
+        v3 := 1
+    else
+        v3 := 2
+    end
+    if v1 >= v0 then v4 := 1
+    if v0 != 11 then v5 := 1
+    loop again
+";
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(compile_octo(source).unwrap()).unwrap();
+    chip8.run_cycles(500).unwrap();
+    assert_eq!(chip8.registers().v[0..6], [11, 55, 1, 2, 1, 0]);
+}
+
+#[test]
+fn compile_octo_errors() {
+    let error = |source: &str| compile_octo(source).unwrap_err().to_string();
+    assert_eq!(error(": main\n  v0 := 300"), "<input>:2:9: Value 300 out of range (-128 to 255)");
+    assert_eq!(error(": main\n  jump nowhere"), "<input>:2:8: Undefined name nowhere");
+    assert_eq!(error(": main\n  loop\n    v0 += 1"), "<input>:2:3: loop without again");
+    assert_eq!(error(": main\n  v0 *= v1"), "<input>:2:6: Invalid operation *= on v0");
+    assert_eq!(error(": main\n  if v0 == 1 clear"), "<input>:2:14: Expected then or begin, found clear");
+    assert_eq!(error(":macro m { m }\n: main m"), "<input>:1:12: Macro m expanded too deeply");
+    assert_eq!(error("clear"), "<input>:1:1: Undefined name main");
+    assert_eq!(error(":calc X { 1 << 70 }"), "<input>:1:13: Shift by 70 out of range (0 to 63)");
+    assert_eq!(error(":calc X { 1 >> -1 }"), "<input>:1:13: Shift by -1 out of range (0 to 63)");
+    assert_eq!(error(": main\n:org 0x1000\n  loop again"), "<input>:3:8: Jump target 4096 out of range (0 to 4095)");
+    assert_eq!(error(": main\n:org 0x1200\n  if v0 == 1 begin clear end"),
+               "<input>:3:26: Jump target 4614 out of range (0 to 4095)");
+}
+
+#[test]
+fn compile_octo_disassembly() {
+    // every instruction word in Octo syntax, skips are followed by a clear
+    for high in 0..16u16 {
+        let mut rom = Vec::new();
+        let mut source = String::from(": main\n");
+        for word in (high << 12)..=(high << 12 | 0xFFF) {
+            let mut instr = Instruction::decode(word);
+            if let Instruction::LdILong { ref mut nnnn } = instr {
+                *nnnn = 0x1234;
+            }
+            let (word, next) = instr.encode();
+            rom.extend_from_slice(&word.to_be_bytes());
+            rom.extend(next.iter().flat_map(|next| next.to_be_bytes().to_vec()));
+            source.push_str(&format!("{}\n", instr.octo()));
+            if instr.is_skip() {
+                rom.extend_from_slice(&[0x00, 0xE0]);
+                source.push_str("clear\n");
+            }
+        }
+        assert_eq!(compile_octo(&source), Ok(rom));
+    }
+}
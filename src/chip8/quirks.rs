@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+/// Effect of Fx55/Fx65 on the I register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    /// I is left unchanged (SUPER-CHIP)
+    None,
+    /// I = I + x (CHIP-48)
+    X,
+    /// I = I + x + 1 (COSMAC VIP, XO-CHIP)
+    XPlusOne,
+}
+
+/// Behavioural differences between CHIP-8 interpreters.
+///
+/// Each switch is independent, presets for known interpreters are available through `QuirksPreset`.
+/// The default matches the historical behaviour of this emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place:
+    /// SHR Vx, Vy => VF = Vy & 1; Vx = Vy >> 1;
+    /// SHL Vx, Vy => VF = Vy >> 7; Vx = Vy << 1;
+    pub shift_uses_vy: bool,
+
+    /// How Fx55/Fx65 update I after the transfer
+    pub memory_increment: MemoryIncrement,
+
+    /// Bnnn is interpreted as BXNN - jump to xnn + Vx - instead of nnn + V0
+    pub jump_uses_vx: bool,
+
+    /// 8xy1/8xy2/8xy3 reset VF to 0
+    pub vf_reset: bool,
+
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    /// The sprite origin always wraps.
+    pub clip_sprites: bool,
+
+    /// Dxyn waits for the next 60Hz vertical blank before drawing,
+    /// limiting the number of sprites drawn per frame to one
+    pub display_wait: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::None,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+}
+
+/// Named quirks profiles of known CHIP-8 interpreters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirksPreset {
+    CosmacVip,
+    Chip48,
+    SuperChip11,
+    XoChip,
+}
+
+impl From<QuirksPreset> for Quirks {
+    fn from(preset: QuirksPreset) -> Self {
+        match preset {
+            QuirksPreset::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                jump_uses_vx: false,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: true,
+            },
+            QuirksPreset::Chip48 => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::X,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            QuirksPreset::SuperChip11 => Quirks {
+                shift_uses_vy: false,
+                memory_increment: MemoryIncrement::None,
+                jump_uses_vx: true,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+            },
+            QuirksPreset::XoChip => Quirks {
+                shift_uses_vy: true,
+                memory_increment: MemoryIncrement::XPlusOne,
+                jump_uses_vx: false,
+                vf_reset: false,
+                clip_sprites: false,
+                display_wait: false,
+            },
+        }
+    }
+}
+
+impl FromStr for QuirksPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" => Ok(QuirksPreset::CosmacVip),
+            "chip48" | "chip-48" => Ok(QuirksPreset::Chip48),
+            "schip" | "superchip" | "schip11" => Ok(QuirksPreset::SuperChip11),
+            "xochip" | "xo-chip" => Ok(QuirksPreset::XoChip),
+            _ => Err(format!("Unknown quirks preset {} (expected vip, chip48, schip or xochip)", s)),
+        }
+    }
+}
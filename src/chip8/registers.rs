@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Registers {
     pub v: Vec<u8>,
     pub i: usize,
@@ -22,4 +23,10 @@ impl Registers {
             st: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new()
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+/// Equal bytes tolerated inside a changed run before it is split in two
+const MERGE_GAP: usize = 8;
+
+/// Snapshot of an older state, stored relative to the state that followed it
+enum Snapshot {
+    /// the older state, when both states differ in size
+    Full(Vec<u8>),
+    /// runs of bytes to write over the newer state to rebuild the older one,
+    /// each run encoded as: skip (u32) | length (u32) | bytes
+    Delta(Vec<u8>),
+}
+
+/// Encode the runs of `older` that differ from `newer`, both slices have the same length
+fn diff(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    let mut last_end = 0;
+    let mut pos = 0;
+    while pos < older.len() {
+        if older[pos] == newer[pos] {
+            pos += 1;
+            continue;
+        }
+
+        let start = pos;
+        let mut end = pos + 1;
+        let mut scan = end;
+        while scan < older.len() && scan - end < MERGE_GAP {
+            if older[scan] != newer[scan] {
+                end = scan + 1;
+            }
+            scan += 1;
+        }
+
+        delta.extend_from_slice(&((start - last_end) as u32).to_be_bytes());
+        delta.extend_from_slice(&((end - start) as u32).to_be_bytes());
+        delta.extend_from_slice(&older[start..end]);
+        last_end = end;
+        pos = end;
+    }
+    delta
+}
+
+/// Apply runs produced by `diff()` to `state`
+fn patch(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut rest = delta;
+    while !rest.is_empty() {
+        let skip = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let len = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+        pos += skip;
+        state[pos..pos + len].copy_from_slice(&rest[8..8 + len]);
+        pos += len;
+        rest = &rest[8 + len..];
+    }
+}
+
+/// Ring buffer of per-frame save states.
+///
+/// The newest state is kept in full, older states are stored as deltas
+/// against the state that followed them. The oldest states are dropped once
+/// `capacity` older states are buffered.
+pub struct RewindBuffer {
+    capacity: usize,
+    current: Option<Vec<u8>>,
+    history: VecDeque<Snapshot>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> RewindBuffer {
+        RewindBuffer {
+            capacity,
+            current: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Maximum number of states that can be rewound to, 0 when rewinding is disabled
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of states that can be rewound to
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Approximate memory used by the buffered states, in bytes
+    pub fn size(&self) -> usize {
+        let current = self.current.as_ref().map_or(0, |state| state.len());
+        self.history.iter().fold(current, |size, snapshot| size + match snapshot {
+            Snapshot::Full(state) => state.len(),
+            Snapshot::Delta(delta) => delta.len(),
+        })
+    }
+
+    /// Record the state of a new frame
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(older) = self.current.take() {
+            let snapshot = if older.len() == state.len() {
+                Snapshot::Delta(diff(&older, &state))
+            } else {
+                Snapshot::Full(older)
+            };
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            self.history.push_back(snapshot);
+        }
+        self.current = Some(state);
+    }
+
+    /// Drop the newest state and return the one recorded before it
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let snapshot = self.history.pop_back()?;
+        let newer = self.current.take().unwrap();
+        let older = match snapshot {
+            Snapshot::Full(state) => state,
+            Snapshot::Delta(delta) => {
+                let mut state = newer;
+                patch(&mut state, &delta);
+                state
+            }
+        };
+        self.current = Some(older.clone());
+        Some(older)
+    }
+}
+
+#[test]
+fn diff_and_patch() {
+    let newer = vec![0u8; 64];
+    let mut older = newer.clone();
+    older[0] = 1;
+    older[5] = 2;
+    older[40] = 3;
+    older[63] = 4;
+
+    let delta = diff(&older, &newer);
+    // runs [0..6], [40..41] and [63..64]
+    assert_eq!(delta.len(), 3 * 8 + 6 + 1 + 1);
+
+    let mut state = newer.clone();
+    patch(&mut state, &delta);
+    assert_eq!(state, older);
+}
+
+#[test]
+fn rewind_buffer_pops_in_reverse_order() {
+    let mut buffer = RewindBuffer::new(2);
+    assert!(buffer.pop().is_none());
+
+    buffer.push(vec![1, 1, 1]);
+    buffer.push(vec![2, 1, 1]);
+    buffer.push(vec![3, 1]);
+    buffer.push(vec![4, 1]);
+    assert_eq!(buffer.len(), 2);
+
+    assert_eq!(buffer.pop(), Some(vec![3, 1]));
+    assert_eq!(buffer.pop(), Some(vec![2, 1, 1]));
+    assert_eq!(buffer.pop(), None);
+
+    buffer.push(vec![5, 1, 1]);
+    assert_eq!(buffer.pop(), Some(vec![2, 1, 1]));
+}
@@ -0,0 +1,169 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// Source of the random bytes returned by Cxkk - RND
+pub trait Rng {
+    /// Next random byte. `memory` is the emulated memory, for generators reading it like `TableRng`
+    fn next_u8(&mut self, memory: &[u8]) -> u8;
+
+    /// Restart the sequence from `seed`, the same seed gives the same sequence
+    fn seed(&mut self, seed: u64);
+
+    /// Internal state, stored in save states
+    fn state(&self) -> Vec<u8>;
+
+    /// Restore a state returned by `state()`, returns false if it is not a valid state for this generator
+    fn set_state(&mut self, state: &[u8]) -> bool;
+}
+
+/// Available generators, see `create()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngKind {
+    XorShift,
+    Table,
+}
+
+impl RngKind {
+    /// Generator of this kind seeded with `seed`, or from the operating system when None
+    pub fn create(self, seed: Option<u64>) -> Box<dyn Rng> {
+        let seed = seed.unwrap_or_else(rand::random);
+        match self {
+            RngKind::XorShift => Box::new(XorShiftRng::new(seed)),
+            RngKind::Table => Box::new(TableRng::new(seed)),
+        }
+    }
+}
+
+impl FromStr for RngKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "xorshift" => Ok(RngKind::XorShift),
+            "table" => Ok(RngKind::Table),
+            _ => Err(format!("Unknown random number generator {} (expected xorshift or table)", s)),
+        }
+    }
+}
+
+/// xorshift64* generator, the default. Reproducible across platforms
+/// and library versions so that seeded runs can be compared
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XorShiftRng {
+    state: u64,
+}
+
+impl XorShiftRng {
+    pub fn new(seed: u64) -> XorShiftRng {
+        let mut rng = XorShiftRng { state: 1 };
+        rng.seed(seed);
+        rng
+    }
+
+    /// Generator seeded from the operating system
+    pub fn from_entropy() -> XorShiftRng {
+        XorShiftRng::new(rand::random())
+    }
+}
+
+impl Rng for XorShiftRng {
+    fn next_u8(&mut self, _memory: &[u8]) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn seed(&mut self, seed: u64) {
+        // splitmix64 spreads small seeds over the state, which must not be 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        self.state = if z == 0 { 1 } else { z };
+    }
+
+    fn state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match <[u8; 8]>::try_from(state) {
+            Ok(bytes) if bytes != [0; 8] => {
+                self.state = u64::from_be_bytes(bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Additive generator: an index incremented on every call selects a byte of the reserved area
+/// at 0x000-0x0FF holding the fonts, which is added with the index to the previous result.
+/// The sequence depends on the font layout and is poor, for ROMs sensitive to weak generators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRng {
+    index: u8,
+    value: u8,
+}
+
+impl TableRng {
+    pub fn new(seed: u64) -> TableRng {
+        let mut rng = TableRng { index: 0, value: 0 };
+        rng.seed(seed);
+        rng
+    }
+}
+
+impl Rng for TableRng {
+    fn next_u8(&mut self, memory: &[u8]) -> u8 {
+        self.index = self.index.wrapping_add(1);
+        let code = memory.get(self.index as usize).copied().unwrap_or(0);
+        self.value = self.value.wrapping_add(code).wrapping_add(self.index);
+        self.value
+    }
+
+    fn seed(&mut self, seed: u64) {
+        self.index = seed as u8;
+        self.value = (seed >> 8) as u8;
+    }
+
+    fn state(&self) -> Vec<u8> {
+        vec![self.index, self.value]
+    }
+
+    fn set_state(&mut self, state: &[u8]) -> bool {
+        match *state {
+            [index, value] => {
+                self.index = index;
+                self.value = value;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[test]
+fn rng_is_reproducible() {
+    let memory: Vec<u8> = (0..=255).collect();
+    for kind in [RngKind::XorShift, RngKind::Table].iter() {
+        let bytes = |seed| {
+            let mut rng = kind.create(Some(seed));
+            (0..8).map(|_| rng.next_u8(&memory)).collect::<Vec<u8>>()
+        };
+        assert_eq!(bytes(1), bytes(1));
+        assert_ne!(bytes(1), bytes(0x201));
+
+        let mut rng = kind.create(Some(7));
+        rng.next_u8(&memory);
+        let state = rng.state();
+        let next = rng.next_u8(&memory);
+        assert!(rng.set_state(&state));
+        assert_eq!(rng.next_u8(&memory), next);
+        assert!(!rng.set_state(&[1, 2, 3]));
+    }
+    assert_ne!(XorShiftRng::new(0).state, 0);
+    assert_eq!("Table".parse::<RngKind>(), Ok(RngKind::Table));
+    assert!("vip".parse::<RngKind>().is_err());
+}
@@ -0,0 +1,72 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Frames the emulation may fall behind before the schedule restarts from the current time,
+/// instead of running the late frames back to back
+const MAX_LAG_FRAMES: u32 = 5;
+
+/// Paces frames against the monotonic clock. Frame n is due `n / frequency` seconds after
+/// the first one, so that sleep overshoot and time spent emulating do not accumulate.
+#[derive(Debug, Clone)]
+pub struct FrameScheduler {
+    frequency: u32,
+    /// time the first frame of the schedule started
+    start: Option<Instant>,
+    /// frames completed since start
+    frame: u64,
+}
+
+impl FrameScheduler {
+    pub fn new(frequency: u32) -> FrameScheduler {
+        FrameScheduler { frequency, start: None, frame: 0 }
+    }
+
+    /// Restart the schedule with the next frame, e.g. after emulation was paused
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.frame = 0;
+    }
+
+    /// Sleep until the next frame is due
+    pub fn wait(&mut self) {
+        if let Some(duration) = self.frame_done(Instant::now()) {
+            sleep(duration);
+        }
+    }
+
+    /// Account for a completed frame at time `now`, returns how long to wait for the next one
+    fn frame_done(&mut self, now: Instant) -> Option<Duration> {
+        let start = *self.start.get_or_insert(now);
+        self.frame += 1;
+        let deadline = start + Duration::from_nanos(self.frame * 1_000_000_000 / self.frequency as u64);
+        if now < deadline {
+            return Some(deadline - now);
+        }
+        if now - deadline > Duration::from_secs(1) * MAX_LAG_FRAMES / self.frequency {
+            self.start = Some(now);
+            self.frame = 0;
+        }
+        None
+    }
+}
+
+#[test]
+fn frame_scheduler_corrects_drift() {
+    let ms = Duration::from_millis;
+    let t0 = Instant::now();
+    let mut scheduler = FrameScheduler::new(50);
+
+    // frames due every 20ms from the first one, however long each frame took
+    assert_eq!(scheduler.frame_done(t0 + ms(5)), Some(ms(20)));
+    assert_eq!(scheduler.frame_done(t0 + ms(27)), Some(ms(18)));
+    // a late frame is caught up by the following ones
+    assert_eq!(scheduler.frame_done(t0 + ms(70)), None);
+    assert_eq!(scheduler.frame_done(t0 + ms(80)), Some(ms(5)));
+
+    // too far behind: restart from now
+    assert_eq!(scheduler.frame_done(t0 + ms(300)), None);
+    assert_eq!(scheduler.frame_done(t0 + ms(310)), Some(ms(10)));
+
+    scheduler.reset();
+    assert_eq!(scheduler.frame_done(t0 + ms(1000)), Some(ms(20)));
+}
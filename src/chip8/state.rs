@@ -0,0 +1,381 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{AUDIO_PATTERN_SIZE, Backend, Chip8, FRAME_FREQUENCY, HIRES_DISPLAY_WIDTH, KBD_SIZE, MemoryIncrement, Quirks, RPL_FLAGS_SIZE};
+use crate::chip8::display::Display;
+use crate::chip8::registers::Registers;
+
+/// Save state file layout:
+/// magic (4 bytes) | version (1 byte) | payload length (u32) | payload | CRC-32 of all previous bytes (u32)
+/// All integers are big-endian.
+const STATE_MAGIC: &[u8; 4] = b"CH8S";
+const STATE_VERSION: u8 = 4;
+const HEADER_SIZE: usize = 4 + 1 + 4;
+const CHECKSUM_SIZE: usize = 4;
+
+/// Number of save slots available to frontends
+pub const SAVE_SLOTS: usize = 10;
+
+#[derive(Debug)]
+pub enum StateError {
+    /// data does not start with the save state magic
+    BadMagic,
+    /// save state written by an incompatible version of the emulator
+    UnsupportedVersion(u8),
+    /// data shorter than announced by its header
+    Truncated,
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// well-formed save state holding an impossible machine state
+    Invalid(&'static str),
+    /// no save path configured or slot outside of 1..=SAVE_SLOTS
+    NoSlot(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "Not a chip8 save state"),
+            StateError::UnsupportedVersion(version) => write!(f, "Unsupported save state version {}", version),
+            StateError::Truncated => write!(f, "Truncated save state"),
+            StateError::ChecksumMismatch { expected, actual } =>
+                write!(f, "Save state checksum mismatch (expected {:08X}, got {:08X})", expected, actual),
+            StateError::Invalid(reason) => write!(f, "Invalid save state: {}", reason),
+            StateError::NoSlot(slot) => write!(f, "Save slot {} is not available", slot),
+            StateError::Io(err) => write!(f, "Save state I/O error: {}", err),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> Self {
+        StateError::Io(err)
+    }
+}
+
+/// CRC-32 (IEEE 802.3) lookup table, one entry per byte value
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+};
+
+/// CRC-32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean out of range")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+fn memory_increment_to_u8(increment: MemoryIncrement) -> u8 {
+    match increment {
+        MemoryIncrement::None => 0,
+        MemoryIncrement::X => 1,
+        MemoryIncrement::XPlusOne => 2,
+    }
+}
+
+fn memory_increment_from_u8(value: u8) -> Result<MemoryIncrement, StateError> {
+    match value {
+        0 => Ok(MemoryIncrement::None),
+        1 => Ok(MemoryIncrement::X),
+        2 => Ok(MemoryIncrement::XPlusOne),
+        _ => Err(StateError::Invalid("unknown memory increment quirk")),
+    }
+}
+
+/// Check the header and checksum of a save state, returns its payload
+fn unwrap_state(data: &[u8]) -> Result<&[u8], StateError> {
+    if data.len() < HEADER_SIZE {
+        return Err(if data.starts_with(&STATE_MAGIC[..data.len().min(4)]) { StateError::Truncated } else { StateError::BadMagic });
+    }
+    if &data[..4] != STATE_MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    if data[4] != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(data[4]));
+    }
+
+    let len = u32::from_be_bytes([data[5], data[6], data[7], data[8]]) as usize;
+    if data.len() < HEADER_SIZE + len + CHECKSUM_SIZE {
+        return Err(StateError::Truncated);
+    }
+
+    let end = HEADER_SIZE + len;
+    let expected = u32::from_be_bytes([data[end], data[end + 1], data[end + 2], data[end + 3]]);
+    let actual = crc32(&data[..end]);
+    if expected != actual {
+        return Err(StateError::ChecksumMismatch { expected, actual });
+    }
+
+    Ok(&data[HEADER_SIZE..end])
+}
+
+impl<B: Backend> Chip8<B> {
+    /// Serialize the full machine state: memory, registers, display, keypad, quirks and audio
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter { data: Vec::new() };
+
+        w.u32(self.memory.len() as u32);
+        w.bytes(&self.memory);
+
+        w.bytes(&self.regs.v);
+        w.u32(self.regs.i as u32);
+        w.u16(self.regs.pc);
+        w.u8(self.regs.sp as u8);
+        for addr in &self.regs.stack {
+            w.u16(*addr);
+        }
+        w.u8(self.regs.dt);
+        w.u8(self.regs.st);
+
+        w.u16(self.display.width() as u16);
+        w.u16(self.display.height() as u16);
+        w.u8(self.display.planes());
+        for row in self.display.pixels() {
+            w.bytes(row);
+        }
+
+        for key in &self.keypad {
+            w.bool(*key);
+        }
+        w.bytes(&self.rpl_flags);
+
+        w.bool(self.quirks.shift_uses_vy);
+        w.u8(memory_increment_to_u8(self.quirks.memory_increment));
+        w.bool(self.quirks.jump_uses_vx);
+        w.bool(self.quirks.vf_reset);
+        w.bool(self.quirks.clip_sprites);
+        w.bool(self.quirks.display_wait);
+
+        w.bytes(self.audio.pattern());
+        w.bool(self.audio.pattern_loaded());
+        w.u8(self.audio.pitch());
+
+        w.bool(self.exited);
+        w.bool(self.vblank);
+        w.u8(self.cycle_remainder as u8);
+        w.u32(self.frame_cycles);
+
+        let rng = self.rng.state();
+        w.u8(rng.len() as u8);
+        w.bytes(&rng);
+
+        let payload = w.data;
+        let mut data = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+        data.extend_from_slice(STATE_MAGIC);
+        data.push(STATE_VERSION);
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(&payload);
+        let checksum = crc32(&data);
+        data.extend_from_slice(&checksum.to_be_bytes());
+        data
+    }
+
+    /// Restore a state produced by `save_state()`.
+    /// The machine is left untouched if the state is rejected.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader { data: unwrap_state(data)? };
+
+        let memory_len = r.u32()? as usize;
+        if memory_len != self.memory.len() {
+            return Err(StateError::Invalid("memory size mismatch"));
+        }
+        let memory = r.bytes(memory_len)?;
+
+        let mut regs = Registers::new();
+        let v_len = regs.v.len();
+        regs.v.copy_from_slice(r.bytes(v_len)?);
+        regs.i = r.u32()? as usize;
+        regs.pc = r.u16()?;
+        regs.sp = r.u8()? as usize;
+        if regs.sp >= regs.stack.len() {
+            return Err(StateError::Invalid("stack pointer out of range"));
+        }
+        for addr in regs.stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        regs.dt = r.u8()?;
+        regs.st = r.u8()?;
+
+        let width = r.u16()? as usize;
+        let height = r.u16()? as usize;
+        let planes = r.u8()?;
+        let mut display = Display::new();
+        display.set_hires(width == HIRES_DISPLAY_WIDTH);
+        if (width, height) != (display.width(), display.height()) {
+            return Err(StateError::Invalid("unsupported display resolution"));
+        }
+        display.select_planes(planes);
+        let pixels = r.bytes(width * height)?;
+        if pixels.iter().any(|pixel| *pixel > 3) {
+            return Err(StateError::Invalid("pixel value out of range"));
+        }
+        display.restore_pixels(pixels);
+
+        let mut keypad = vec![false; KBD_SIZE];
+        for key in keypad.iter_mut() {
+            *key = r.bool()?;
+        }
+        let rpl_flags = r.bytes(RPL_FLAGS_SIZE)?;
+
+        let quirks = Quirks {
+            shift_uses_vy: r.bool()?,
+            memory_increment: memory_increment_from_u8(r.u8()?)?,
+            jump_uses_vx: r.bool()?,
+            vf_reset: r.bool()?,
+            clip_sprites: r.bool()?,
+            display_wait: r.bool()?,
+        };
+
+        let pattern = r.bytes(AUDIO_PATTERN_SIZE)?;
+        let pattern_loaded = r.bool()?;
+        let pitch = r.u8()?;
+
+        let exited = r.bool()?;
+        let vblank = r.bool()?;
+        let cycle_remainder = r.u8()? as u32;
+        if cycle_remainder >= FRAME_FREQUENCY {
+            return Err(StateError::Invalid("cycle remainder out of range"));
+        }
+        let frame_cycles = r.u32()?;
+
+        let rng_len = r.u8()? as usize;
+        let rng = r.bytes(rng_len)?;
+
+        if !r.data.is_empty() {
+            return Err(StateError::Invalid("trailing data"));
+        }
+        if !self.rng.set_state(rng) {
+            return Err(StateError::Invalid("random number generator state mismatch"));
+        }
+
+        self.memory.copy_from_slice(memory);
+        self.regs = regs;
+        self.display = display;
+        self.keypad = keypad;
+        self.rpl_flags.copy_from_slice(rpl_flags);
+        self.quirks = quirks;
+        self.audio.restore(pattern, pattern_loaded, pitch);
+        self.exited = exited;
+        self.vblank = vblank;
+        self.cycle_remainder = cycle_remainder;
+        self.frame_cycles = frame_cycles;
+
+        Ok(())
+    }
+
+    /// Set the path save slots are derived from, usually the ROM path.
+    /// Slot n is stored in `<path>.state<n>`.
+    pub fn set_save_path<P: AsRef<Path>>(&mut self, path: P) {
+        self.save_path = Some(path.as_ref().to_path_buf());
+    }
+
+    fn slot_path(&self, slot: usize) -> Result<PathBuf, StateError> {
+        match &self.save_path {
+            Some(path) if (1..=SAVE_SLOTS).contains(&slot) => {
+                let mut name = path.as_os_str().to_os_string();
+                name.push(format!(".state{}", slot));
+                Ok(PathBuf::from(name))
+            }
+            _ => Err(StateError::NoSlot(slot)),
+        }
+    }
+
+    /// Write the current state to save slot `slot` (1..=SAVE_SLOTS)
+    pub fn save_slot(&self, slot: usize) -> Result<(), StateError> {
+        fs::write(self.slot_path(slot)?, self.save_state())?;
+        Ok(())
+    }
+
+    /// Restore the state stored in save slot `slot` (1..=SAVE_SLOTS)
+    pub fn load_slot(&mut self, slot: usize) -> Result<(), StateError> {
+        let data = fs::read(self.slot_path(slot)?)?;
+        self.load_state(&data)
+    }
+}
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
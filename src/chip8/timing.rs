@@ -0,0 +1,95 @@
+use std::str::FromStr;
+
+/// How long instructions take, see `Chip8::set_timing()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    /// Every instruction takes the same time, a fixed number of instructions
+    /// is executed per frame, see `Chip8::set_cpu_frequency()`
+    Fixed,
+    /// Instructions take as many machine cycles as on the COSMAC VIP interpreter
+    /// and Dxyn always waits for the vertical blank interrupt
+    CosmacVip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Timing::Fixed),
+            "vip" | "cosmac-vip" => Ok(Timing::CosmacVip),
+            _ => Err(format!("Unknown timing {} (expected fixed or vip)", s)),
+        }
+    }
+}
+
+/// The 1802 runs at 1.7609 MHz with 8 clock pulses per machine cycle: 3668 machine cycles per 60Hz frame
+pub const VIP_FRAME_CYCLES: u32 = 3668;
+/// Machine cycles stolen every frame by the CDP1861 display DMA: 8 bytes for each of the 128 scanlines
+const VIP_DMA_CYCLES: u32 = 128 * 8;
+/// Machine cycles of the interrupt routine refreshing the display pointer and decrementing the timers
+const VIP_INTERRUPT_CYCLES: u32 = 52;
+/// Machine cycles left to the interpreter every frame
+pub const VIP_CPU_CYCLES_PER_FRAME: u32 = VIP_FRAME_CYCLES - VIP_DMA_CYCLES - VIP_INTERRUPT_CYCLES;
+
+/// Fetching and decoding an instruction, charged on top of every instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// Machine cycles taken by the VIP interpreter to execute `instr`, `vx` being the value of Vx
+/// before execution and `skipped` true if the instruction skipped the next one.
+/// Costs are those of the interpreter routines, rounded; a Dxyn waiting for the interrupt is
+/// charged by the caller. Instructions the VIP does not have only cost the fetch and decode.
+pub fn vip_cycles(instr: u16, vx: u8, skipped: bool) -> u32 {
+    let x = (instr >> 8 & 0xF) as u32;
+    let n = (instr & 0xF) as u32;
+    let skip = if skipped { 4 } else { 0 };
+    let execute = match instr >> 12 {
+        0x0 => match instr {
+            // clears the 256 display bytes
+            0x00E0 => 3078,
+            0x00EE => 10,
+            _ => 0,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10 + skip,
+        0x5 | 0x9 if n == 0 => 14 + skip,
+        0x6 => 6,
+        0x7 => 10,
+        // executed through a generated 1802 instruction
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        // each row is shifted into one byte when byte-aligned, two otherwise
+        0xD => 26 + n * if vx.is_multiple_of(8) { 34 } else { 54 },
+        0xE => 14 + skip,
+        0xF => match instr & 0xFF {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 20,
+            0x1E | 0x29 => 16,
+            // repeated subtractions, one per unit of each decimal digit
+            0x33 => 80 + 16 * (vx as u32 / 100 + vx as u32 / 10 % 10 + vx as u32 % 10),
+            0x55 | 0x65 => 14 + 14 * (x + 1),
+            _ => 0,
+        },
+        _ => 0,
+    };
+    VIP_FETCH_CYCLES + execute
+}
+
+#[test]
+fn vip_instruction_cycles() {
+    assert_eq!(vip_cycles(0x6012, 0, false), 46);
+    assert_eq!(vip_cycles(0x3012, 0x12, true), 54);
+    assert_eq!(vip_cycles(0x3012, 0, false), 50);
+    assert_eq!(vip_cycles(0xD125, 8, false), 40 + 26 + 5 * 34);
+    assert_eq!(vip_cycles(0xD125, 9, false), 40 + 26 + 5 * 54);
+    assert_eq!(vip_cycles(0xF333, 199, false), 40 + 80 + 16 * 19);
+    assert_eq!(vip_cycles(0xF255, 0, false), 40 + 14 + 14 * 3);
+    assert_eq!(vip_cycles(0x00FF, 0, false), 40);
+    assert_eq!(vip_cycles(0x5120, 0, true), 40 + 14 + 4);
+    assert_eq!(vip_cycles(0x5122, 0, false), 40);
+    assert_eq!(VIP_CPU_CYCLES_PER_FRAME, 2592);
+    assert_eq!("VIP".parse::<Timing>(), Ok(Timing::CosmacVip));
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::Write;
+
+use crate::chip8::{EmulatorError, Instruction, Registers};
+
+/// Per-instruction execution log, for diffing against other emulators.
+///
+/// Each line holds the state before the instruction executes:
+/// `cycle PC opcode mnemonic V0-VF I SP DT ST`, e.g.
+/// `0000000042 PC=20A OP=D015     DRW V0, V1, 5            V=00 01 ... 00 I=0214 SP=0 DT=00 ST=00`
+pub struct Tracer {
+    output: Box<dyn Write>,
+    /// inclusive PC ranges to log, everything when empty
+    ranges: Vec<(u16, u16)>,
+    /// last lines kept until an error when logging only the last N instructions
+    ring: Option<(VecDeque<String>, usize)>,
+    /// instructions executed since tracing started, including filtered ones
+    cycle: u64,
+    /// first write error, tracing stops after it
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// Log every executed instruction to `output`
+    pub fn new(output: Box<dyn Write>) -> Tracer {
+        Tracer { output, ranges: Vec::new(), ring: None, cycle: 0, error: None }
+    }
+
+    /// Only log instructions with PC in `start..=end`, can be called several times
+    pub fn add_range(&mut self, start: u16, end: u16) {
+        self.ranges.push((start, end));
+    }
+
+    /// Keep only the last `n` instructions in memory, written out when an instruction fails
+    pub fn set_ring_buffer(&mut self, n: usize) {
+        self.ring = Some((VecDeque::with_capacity(n), n));
+    }
+
+    /// Number of instructions executed since tracing started
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Write error that stopped tracing, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Log the instruction about to execute at PC
+    pub fn trace(&mut self, regs: &Registers, memory: &[u8]) {
+        self.cycle += 1;
+        if self.error.is_some() {
+            return;
+        }
+        let pc = regs.pc;
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|(start, end)| (*start..=*end).contains(&pc)) {
+            return;
+        }
+
+        let line = Tracer::format(self.cycle, regs, memory);
+        match self.ring {
+            Some((ref mut lines, capacity)) => {
+                if lines.len() == capacity {
+                    lines.pop_front();
+                }
+                if capacity > 0 {
+                    lines.push_back(line);
+                }
+            }
+            None => {
+                let result = writeln!(self.output, "{}", line);
+                self.check(result);
+            }
+        }
+    }
+
+    /// Write the buffered instructions followed by the error
+    pub fn dump(&mut self, error: &EmulatorError) {
+        if self.error.is_some() {
+            return;
+        }
+        let lines: Vec<String> = self.ring.as_mut().map(|(lines, _)| lines.drain(..).collect()).unwrap_or_default();
+        let mut result = Ok(());
+        for line in lines {
+            result = result.and_then(|_| writeln!(self.output, "{}", line));
+        }
+        let result = result
+            .and_then(|_| writeln!(self.output, "error: {}", error))
+            .and_then(|_| self.output.flush());
+        self.check(result);
+    }
+
+    /// Flush buffered output
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn check(&mut self, result: io::Result<()>) {
+        if let Err(err) = result {
+            self.error = Some(err);
+        }
+    }
+
+    fn format(cycle: u64, regs: &Registers, memory: &[u8]) -> String {
+        let instr = Instruction::read(memory, regs.pc as usize);
+        let opcode = match instr.encode() {
+            (word, Some(next)) => format!("{:04X}{:04X}", word, next),
+            (word, None) => format!("{:04X}", word),
+        };
+        let v: Vec<String> = regs.v.iter().map(|v| format!("{:02X}", v)).collect();
+        format!("{:010} PC={:03X} OP={:<8} {:<24} V={} I={:04X} SP={:X} DT={:02X} ST={:02X}",
+                cycle, regs.pc, opcode, instr.to_string(), v.join(" "), regs.i, regs.sp, regs.dt, regs.st)
+    }
+}
+
+#[test]
+fn trace_format() {
+    let mut regs = Registers::new();
+    regs.v[1] = 0xAB;
+    regs.i = 0x214;
+    regs.dt = 3;
+    let memory = [0u8; 0x200].iter().chain(&[0xD0, 0x15, 0xF0, 0x00, 0x12, 0x34]).copied().collect::<Vec<u8>>();
+    assert_eq!(Tracer::format(42, &regs, &memory),
+               "0000000042 PC=200 OP=D015     DRW V0, V1, 5            \
+                V=00 AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I=0214 SP=0 DT=03 ST=00");
+    regs.pc = 0x202;
+    assert!(Tracer::format(43, &regs, &memory).starts_with("0000000043 PC=202 OP=F0001234 LD I, LONG #1234         V="));
+}
@@ -0,0 +1,335 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use chip8::chip8::{Backend, Breakpoint, Chip8, Condition, EmulatorError, Instruction, Register, WatchKind};
+
+const HELP: &str = "Commands:
+    b, break <addr> [hits <n>] [if <condition>]
+                                set a breakpoint, optionally breaking only on the n-th hit
+                                (decimal) and when a condition such as v3 == 0x10 && i > 0x300
+                                or mem[0x3F0] != 0 holds
+    b, break <reg>=<value>      break when v0-vf or i changes to value
+    d, delete <addr>|<reg>      remove a breakpoint
+    w, watch <addr> [len] [r|w|rw]
+                                break on memory reads and/or writes (default 1 byte, rw)
+    unwatch <addr>              remove a watchpoint
+    l, list                     list breakpoints and watchpoints
+    s, step [n]                 execute n instructions (default 1), timers are not ticked
+    c, continue                 run until a breakpoint, F12 in the window breaks
+    r, regs                     print registers and timers
+    set <reg> <value>           set v0-vf, i, pc, sp, dt or st
+    bt, stack                   print the call stack
+    x <addr> [len]              hex dump memory (default 64 bytes)
+    dis [addr] [n]              disassemble n instructions (default 10) around PC or from addr
+    h, help                     print this help
+    q, quit                     quit the emulator
+Numbers are hexadecimal, with or without a 0x prefix.";
+
+/// Refresh interval of the window while waiting for a command
+const IDLE_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Outcome of a debugger command
+enum Action {
+    Stay,
+    Continue,
+    Quit,
+}
+
+fn parse_hex(value: Option<&str>) -> Result<usize, String> {
+    let value = value.ok_or("Missing argument")?;
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X").trim_start_matches('#');
+    usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid number {}", value))
+}
+
+/// Read stdin lines on a separate thread so the window keeps refreshing while waiting
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Interactive debugger: a command prompt on the terminal driving a paused `Chip8`
+pub struct Repl {
+    input: Receiver<String>,
+    /// repeated on an empty line
+    last_command: String,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            input: spawn_stdin_reader(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Run `chip8` under the debugger, starting paused at PC
+    pub fn run<B: Backend>(&mut self, chip8: &mut Chip8<B>) -> Result<(), EmulatorError> {
+        println!("Chip8 debugger, type help for a list of commands");
+        print_current(chip8);
+        loop {
+            let line = match self.read_line(chip8) {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+
+            let action = match self.exec(chip8, &line) {
+                Ok(action) => action,
+                Err(err) => {
+                    println!("{}", err);
+                    Action::Stay
+                }
+            };
+
+            match action {
+                Action::Stay => {}
+                Action::Quit => return Ok(()),
+                Action::Continue => {
+                    chip8.resume();
+                    let result = chip8.run();
+                    if chip8.quit_requested() {
+                        return Ok(());
+                    }
+                    // stay in the debugger on a fault, PC is left at the faulting instruction
+                    match (result, chip8.break_reason()) {
+                        (Err(err), _) => println!("{}", err),
+                        (Ok(()), Some(reason)) => println!("{}", reason),
+                        (Ok(()), None) if chip8.exited() => println!("Program exited"),
+                        (Ok(()), None) => {}
+                    }
+                    print_current(chip8);
+                }
+            }
+        }
+    }
+
+    /// Prompt for a command, keeping the window alive until one is entered.
+    /// Returns None on end of input or once the user closed the window.
+    fn read_line<B: Backend>(&mut self, chip8: &mut Chip8<B>) -> Option<String> {
+        print!("(chip8) ");
+        io::stdout().flush().unwrap();
+        loop {
+            match self.input.try_recv() {
+                Ok(line) => {
+                    let line = line.trim().to_string();
+                    if !line.is_empty() {
+                        self.last_command = line;
+                    }
+                    return Some(self.last_command.clone());
+                }
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {
+                    if !chip8.refresh() {
+                        return None;
+                    }
+                    thread::sleep(IDLE_INTERVAL);
+                }
+            }
+        }
+    }
+
+    fn exec<B: Backend>(&mut self, chip8: &mut Chip8<B>, line: &str) -> Result<Action, String> {
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(Action::Stay),
+        };
+
+        match command {
+            "b" | "break" => {
+                let arg = args.next().ok_or("Missing argument")?;
+                if let Some(pos) = arg.find('=') {
+                    let reg = arg[..pos].parse::<Register>()?;
+                    let value = parse_hex(Some(&arg[pos + 1..]))?;
+                    chip8.debugger_mut().add_register_breakpoint(reg, value);
+                    println!("Breakpoint set on {} = {:X}", reg, value);
+                } else {
+                    let addr = parse_hex(Some(arg))? as u16;
+                    let breakpoint = parse_breakpoint(args)?;
+                    chip8.debugger_mut().set_breakpoint(addr, breakpoint);
+                    println!("Breakpoint set at {:03X}", addr);
+                }
+            }
+            "d" | "delete" => {
+                let arg = args.next().ok_or("Missing argument")?;
+                let removed = match arg.parse::<Register>() {
+                    Ok(reg) => chip8.debugger_mut().remove_register_breakpoint(reg),
+                    Err(_) => chip8.debugger_mut().remove_breakpoint(parse_hex(Some(arg))? as u16),
+                };
+                if !removed {
+                    return Err(format!("No breakpoint on {}", arg));
+                }
+            }
+            "w" | "watch" => {
+                let addr = check_addr(chip8, parse_hex(args.next())?)?;
+                // watch up to the end of memory
+                let len = args.next().map_or(Ok(1), |len| parse_hex(Some(len)))?.min(chip8.memory().len() - addr);
+                let kind = args.next().map_or(Ok(WatchKind::ReadWrite), |kind| kind.parse::<WatchKind>())?;
+                chip8.debugger_mut().add_watchpoint(addr, len, kind);
+                println!("Watchpoint set on {:03X}-{:03X}", addr, addr + len.max(1) - 1);
+            }
+            "unwatch" => {
+                let addr = parse_hex(args.next())?;
+                if !chip8.debugger_mut().remove_watchpoint(addr) {
+                    return Err(format!("No watchpoint at {:03X}", addr));
+                }
+            }
+            "l" | "list" => {
+                let debugger = chip8.debugger();
+                for (addr, breakpoint) in debugger.breakpoints() {
+                    println!("break {:03X} {}", addr, breakpoint);
+                }
+                for (reg, value) in debugger.register_breakpoints() {
+                    println!("break {} = {:X}", reg, value);
+                }
+                for watchpoint in debugger.watchpoints() {
+                    println!("watch {:03X} len {:X} {:?}", watchpoint.start, watchpoint.len, watchpoint.kind);
+                }
+            }
+            "s" | "step" => {
+                let n = args.next().map_or(Ok(1), |n| parse_hex(Some(n)))?;
+                for _ in 0..n {
+                    if chip8.exited() {
+                        break;
+                    }
+                    chip8.step().map_err(|err| err.to_string())?;
+                }
+                print_current(chip8);
+            }
+            "c" | "continue" if chip8.exited() => return Err("Program exited".to_string()),
+            "c" | "continue" => return Ok(Action::Continue),
+            "r" | "regs" => print_registers(chip8),
+            "set" => set_register(chip8, args.next(), parse_hex(args.next())?)?,
+            "bt" | "stack" => {
+                // innermost frame first, then the return addresses
+                let regs = chip8.registers();
+                println!("#0  {:03X}", regs.pc);
+                for level in (0..regs.sp).rev() {
+                    println!("#{:<2} {:03X}", regs.sp - level, regs.stack[level]);
+                }
+            }
+            "x" => {
+                let addr = check_addr(chip8, parse_hex(args.next())?)?;
+                let len = args.next().map_or(Ok(64), |len| parse_hex(Some(len)))?;
+                dump_memory(chip8.memory(), addr, len);
+            }
+            "dis" => {
+                let pc = chip8.registers().pc;
+                let addr = args.next().map_or(Ok(pc.saturating_sub(8) as usize), |addr| parse_hex(Some(addr)))?;
+                let mut addr = check_addr(chip8, addr)?;
+                // stop at the end of memory, there is at most one instruction per byte left
+                let end = chip8.memory().len();
+                let n = args.next().map_or(Ok(10), |n| parse_hex(Some(n)))?.min(end - addr);
+                for _ in 0..n {
+                    if addr >= end {
+                        break;
+                    }
+                    addr += print_instr(chip8, addr);
+                }
+            }
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(Action::Quit),
+            _ => return Err(format!("Unknown command {}, type help for a list of commands", command)),
+        }
+        Ok(Action::Stay)
+    }
+}
+
+/// Parse the `[hits <n>] [if <condition>]` options of a breakpoint
+fn parse_breakpoint<'a>(mut args: impl Iterator<Item=&'a str>) -> Result<Breakpoint, String> {
+    let mut hit_count = 0;
+    let mut condition = None;
+    while let Some(arg) = args.next() {
+        match arg {
+            "hits" => {
+                let n = args.next().ok_or("Missing hit count")?;
+                hit_count = n.parse::<u32>().map_err(|_| format!("Invalid hit count {}", n))?;
+            }
+            "if" => {
+                let source = args.by_ref().collect::<Vec<&str>>().join(" ");
+                condition = Some(source.parse::<Condition>()?);
+            }
+            _ => return Err(format!("Unexpected {}, expected hits or if", arg)),
+        }
+    }
+    Ok(Breakpoint::new(condition, hit_count))
+}
+
+fn set_register<B: Backend>(chip8: &mut Chip8<B>, reg: Option<&str>, value: usize) -> Result<(), String> {
+    let reg = reg.ok_or("Missing register")?.to_ascii_lowercase();
+    let mem_size = chip8.memory().len();
+    let regs = chip8.registers_mut();
+    match reg.as_str() {
+        "i" if value < mem_size => regs.i = value,
+        "pc" => regs.pc = value as u16,
+        "sp" if value < regs.stack.len() => regs.sp = value,
+        "dt" => regs.dt = value as u8,
+        "st" => regs.st = value as u8,
+        _ if reg.len() == 2 && reg.starts_with('v') => {
+            let x = usize::from_str_radix(&reg[1..], 16).map_err(|_| format!("Unknown register {}", reg))?;
+            regs.v[x] = value as u8;
+        }
+        _ => return Err(format!("Cannot set {} to {:X}", reg, value)),
+    }
+    Ok(())
+}
+
+fn print_registers<B: Backend>(chip8: &Chip8<B>) {
+    let regs = chip8.registers();
+    for (x, value) in regs.v.iter().enumerate() {
+        print!("V{:X}={:02X}{}", x, value, if x % 8 == 7 { "\n" } else { " " });
+    }
+    println!("I={:03X} PC={:03X} SP={:X} DT={:02X} ST={:02X}", regs.i, regs.pc, regs.sp, regs.dt, regs.st);
+}
+
+/// Print the instruction at `addr`, returns its size
+fn print_instr<B: Backend>(chip8: &Chip8<B>, addr: usize) -> usize {
+    let marker = if addr == chip8.registers().pc as usize { "=>" } else { "  " };
+    let instr = Instruction::read(chip8.memory(), addr);
+    let words = match instr.encode() {
+        (word, Some(next)) => format!("{:04X} {:04X}", word, next),
+        (word, None) => format!("{:04X}     ", word),
+    };
+    println!("{} {:03X}: {}  {}", marker, addr, words, instr);
+    instr.size()
+}
+
+/// Print the instruction at PC
+fn print_current<B: Backend>(chip8: &Chip8<B>) {
+    print_instr(chip8, chip8.registers().pc as usize);
+}
+
+/// Fail if `addr` is past the end of memory
+fn check_addr<B: Backend>(chip8: &Chip8<B>, addr: usize) -> Result<usize, String> {
+    if addr >= chip8.memory().len() {
+        return Err(format!("Address {:X} is outside of memory", addr));
+    }
+    Ok(addr)
+}
+
+/// Print `len` bytes starting at `addr`, 16 per line
+fn dump_memory(memory: &[u8], addr: usize, len: usize) {
+    let end = addr.saturating_add(len).min(memory.len());
+    let mut line = addr;
+    while line < end {
+        let bytes = &memory[line..(line + 16).min(end)];
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let ascii: String = bytes.iter()
+            .map(|byte| if byte.is_ascii_graphic() { *byte as char } else { '.' })
+            .collect();
+        println!("{:04X}: {:<47}  {}", line, hex.join(" "), ascii);
+        line += 16;
+    }
+}
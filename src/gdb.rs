@@ -0,0 +1,443 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Range;
+use std::time::Duration;
+
+use chip8::chip8::{Backend, BreakReason, Chip8, EmulatorError};
+
+/// Register numbers used by the `g`, `G`, `p` and `P` packets, in target description order.
+/// V0-VF, SP, DT and ST are 8 bits wide, I and PC 16 bits, multi-byte values are sent little-endian.
+const REG_I: usize = 16;
+const REG_PC: usize = 17;
+const REG_SP: usize = 18;
+const REG_DT: usize = 19;
+const REG_ST: usize = 20;
+const REG_COUNT: usize = 21;
+
+/// GDB signal numbers reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGABRT: u8 = 6;
+const SIGSEGV: u8 = 11;
+
+/// Refresh interval of the window while waiting for a packet
+const IDLE_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Interrupt request sent by GDB outside of a packet (Ctrl-C)
+const INTERRUPT: u8 = 0x03;
+
+fn reg_size(reg: usize) -> usize {
+    match reg {
+        REG_I | REG_PC => 2,
+        _ => 1,
+    }
+}
+
+/// Target description naming the CHIP-8 register file for GDB clients
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n\
+                                <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n\
+                                <target version=\"1.0\">\n\
+                                <feature name=\"org.chip8.core\">\n");
+    for x in 0..16 {
+        xml.push_str(&format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\" regnum=\"{}\"/>\n", x, x));
+    }
+    xml.push_str("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>\n\
+                  <reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\n\
+                  <reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\n\
+                  <reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>\n\
+                  <reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>\n\
+                  </feature>\n\
+                  </target>\n");
+    xml
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|pos| u8::from_str_radix(hex.get(pos..pos + 2)?, 16).ok()).collect()
+}
+
+fn parse_hex(hex: &str) -> Option<usize> {
+    usize::from_str_radix(hex, 16).ok()
+}
+
+/// Parse `addr,len`
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+/// `addr..addr + len` if it ends within `max` bytes, None on overflow
+fn mem_range(addr: usize, len: usize, max: usize) -> Option<Range<usize>> {
+    let end = addr.checked_add(len)?;
+    if end <= max { Some(addr..end) } else { None }
+}
+
+fn read_register<B: Backend>(chip8: &Chip8<B>, reg: usize) -> Vec<u8> {
+    let regs = chip8.registers();
+    match reg {
+        0..=15 => vec![regs.v[reg]],
+        REG_I => (regs.i as u16).to_le_bytes().to_vec(),
+        REG_PC => regs.pc.to_le_bytes().to_vec(),
+        REG_SP => vec![regs.sp as u8],
+        REG_DT => vec![regs.dt],
+        _ => vec![regs.st],
+    }
+}
+
+fn write_register<B: Backend>(chip8: &mut Chip8<B>, reg: usize, bytes: &[u8]) -> bool {
+    let regs = chip8.registers_mut();
+    let value = if bytes.len() == 2 { u16::from_le_bytes([bytes[0], bytes[1]]) } else { bytes[0] as u16 };
+    match reg {
+        0..=15 => regs.v[reg] = value as u8,
+        REG_I => regs.i = value as usize,
+        REG_PC => regs.pc = value,
+        REG_SP if (value as usize) < regs.stack.len() => regs.sp = value as usize,
+        REG_DT => regs.dt = value as u8,
+        REG_ST => regs.st = value as u8,
+        _ => return false,
+    }
+    true
+}
+
+/// Signal reported to GDB for an emulation error
+fn error_signal(err: &EmulatorError) -> u8 {
+    match err {
+        EmulatorError::UnknownOpcode { .. } => SIGILL,
+        EmulatorError::MemoryOutOfBounds { .. } => SIGSEGV,
+        _ => SIGABRT,
+    }
+}
+
+/// Packet framing over a TCP connection: `$payload#checksum`, acknowledged with `+`
+struct Connection {
+    stream: TcpStream,
+    /// bytes received but not yet consumed
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    /// Receive pending bytes, returns None if none arrived before the read timeout
+    /// and Some(false) once the client disconnected
+    fn fill(&mut self) -> io::Result<Option<bool>> {
+        let mut chunk = [0; 1024];
+        match self.stream.read(&mut chunk) {
+            Ok(len) => {
+                self.buffer.extend_from_slice(&chunk[..len]);
+                Ok(Some(len > 0))
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut =>
+                Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Next packet payload, None once the client disconnected.
+    /// `idle` is called while waiting for it.
+    fn read_packet(&mut self, mut idle: impl FnMut()) -> io::Result<Option<String>> {
+        loop {
+            // drop acks and stray interrupts, GDB only sends them while the target runs
+            while let Some(byte) = self.buffer.first() {
+                if *byte == b'$' {
+                    break;
+                }
+                self.buffer.remove(0);
+            }
+
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'#') {
+                if self.buffer.len() >= end + 3 {
+                    let packet: Vec<u8> = self.buffer.drain(..end + 3).collect();
+                    let payload = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..]).ok().and_then(parse_hex);
+                    let actual = payload.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+                    if checksum != Some(actual as usize) {
+                        self.stream.write_all(b"-")?;
+                        continue;
+                    }
+                    self.stream.write_all(b"+")?;
+                    return Ok(Some(String::from_utf8_lossy(payload).into_owned()));
+                }
+            }
+
+            match self.fill()? {
+                Some(true) => {}
+                Some(false) => return Ok(None),
+                None => idle(),
+            }
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> io::Result<()> {
+        let checksum = payload.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.stream.write_all(format!("${}#{:02x}", payload, checksum).as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Returns true if GDB asked to interrupt the target, without blocking
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let result = self.fill();
+        self.stream.set_nonblocking(false)?;
+        result?;
+
+        match self.buffer.iter().position(|byte| *byte == INTERRUPT) {
+            Some(pos) => {
+                self.buffer.remove(pos);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Outcome of a packet
+enum Reply {
+    Packet(String),
+    /// the client detached or killed the target, stop serving
+    Close(String),
+}
+
+/// GDB remote serial protocol server on a local TCP port
+pub struct GdbStub {
+    listener: TcpListener,
+}
+
+impl GdbStub {
+    /// Listen on `127.0.0.1:port`, port 0 picks a free port
+    pub fn bind(port: u16) -> io::Result<GdbStub> {
+        Ok(GdbStub { listener: TcpListener::bind(("127.0.0.1", port))? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Wait for a GDB client, then debug `chip8` until it detaches.
+    /// The target is stopped at PC when the client attaches.
+    pub fn serve<B: Backend>(&self, chip8: &mut Chip8<B>) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        // wake up regularly to keep the window alive while stopped
+        stream.set_read_timeout(Some(IDLE_INTERVAL))?;
+        let mut connection = Connection { stream, buffer: Vec::new() };
+
+        while let Some(packet) = connection.read_packet(|| { chip8.refresh(); })? {
+            match handle_packet(chip8, &mut connection, &packet)? {
+                Reply::Packet(reply) => connection.send(&reply)?,
+                Reply::Close(reply) => {
+                    connection.send(&reply)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_packet<B: Backend>(chip8: &mut Chip8<B>, connection: &mut Connection,
+                             packet: &str) -> io::Result<Reply> {
+    let error = || Reply::Packet("E01".to_string());
+    let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+
+    let reply = match command {
+        "?" => format!("S{:02x}", SIGTRAP),
+
+        "g" => (0..REG_COUNT).map(|reg| to_hex(&read_register(chip8, reg))).collect(),
+        "G" => {
+            let bytes = match from_hex(args) {
+                Some(bytes) => bytes,
+                None => return Ok(error()),
+            };
+            let mut pos = 0;
+            for reg in 0..REG_COUNT {
+                let size = reg_size(reg);
+                if pos + size > bytes.len() {
+                    break;
+                }
+                write_register(chip8, reg, &bytes[pos..pos + size]);
+                pos += size;
+            }
+            "OK".to_string()
+        }
+        "p" => match parse_hex(args) {
+            Some(reg) if reg < REG_COUNT => to_hex(&read_register(chip8, reg)),
+            _ => return Ok(error()),
+        },
+        "P" => {
+            let mut parts = args.splitn(2, '=');
+            let reg = parts.next().and_then(parse_hex);
+            let bytes = parts.next().and_then(from_hex);
+            match (reg, bytes) {
+                (Some(reg), Some(bytes)) if reg < REG_COUNT && bytes.len() == reg_size(reg)
+                    && write_register(chip8, reg, &bytes) => "OK".to_string(),
+                _ => return Ok(error()),
+            }
+        }
+
+        "m" => match parse_range(args).and_then(|(addr, len)| mem_range(addr, len, chip8.memory().len())) {
+            Some(range) => to_hex(&chip8.memory()[range]),
+            None => return Ok(error()),
+        },
+        "M" => {
+            let mut parts = args.splitn(2, ':');
+            let range = parts.next().and_then(parse_range)
+                .and_then(|(addr, len)| mem_range(addr, len, chip8.memory().len()));
+            let bytes = parts.next().and_then(from_hex);
+            match (range, bytes) {
+                (Some(range), Some(bytes)) if bytes.len() == range.len() => {
+                    chip8.memory_mut()[range].copy_from_slice(&bytes);
+                    "OK".to_string()
+                }
+                _ => return Ok(error()),
+            }
+        }
+
+        // Z0,addr,kind / z0,addr,kind - insert / remove a software breakpoint
+        "Z" | "z" if args.starts_with("0,") => {
+            match args[2..].split(',').next().and_then(parse_hex) {
+                Some(addr) if command == "Z" => {
+                    chip8.debugger_mut().add_breakpoint(addr as u16);
+                }
+                Some(addr) => {
+                    chip8.debugger_mut().remove_breakpoint(addr as u16);
+                }
+                None => return Ok(error()),
+            }
+            "OK".to_string()
+        }
+
+        "s" => {
+            if let Some(addr) = parse_hex(args) {
+                chip8.registers_mut().pc = addr as u16;
+            }
+            match chip8.step() {
+                Ok(_) => format!("S{:02x}", SIGTRAP),
+                Err(err) => format!("S{:02x}", error_signal(&err)),
+            }
+        }
+        "c" => {
+            if let Some(addr) = parse_hex(args) {
+                chip8.registers_mut().pc = addr as u16;
+            }
+            return cont(chip8, connection);
+        }
+
+        "D" => return Ok(Reply::Close("OK".to_string())),
+        "k" => return Ok(Reply::Close(format!("X{:02x}", SIGABRT))),
+        "H" => "OK".to_string(),
+
+        "q" if args.starts_with("Supported") => "PacketSize=1000;qXfer:features:read+".to_string(),
+        "q" if args == "Attached" => "1".to_string(),
+        "q" if args == "C" => String::new(),
+        "q" if args.starts_with("Xfer:features:read:target.xml:") => {
+            let xml = target_xml();
+            // reads past the end are cut short
+            let range = parse_range(&args["Xfer:features:read:target.xml:".len()..])
+                .and_then(|(offset, len)| mem_range(offset, len.min(xml.len().saturating_sub(offset)), xml.len()));
+            match range {
+                Some(range) => {
+                    let more = if range.end < xml.len() { "m" } else { "l" };
+                    format!("{}{}", more, &xml[range])
+                }
+                None => return Ok(error()),
+            }
+        }
+
+        // unsupported packets get an empty reply
+        _ => String::new(),
+    };
+    Ok(Reply::Packet(reply))
+}
+
+/// Run in real time until a breakpoint, an error, the program exit or an interrupt from GDB
+fn cont<B: Backend>(chip8: &mut Chip8<B>, connection: &mut Connection) -> io::Result<Reply> {
+    chip8.resume();
+    loop {
+        match chip8.run_realtime_frame() {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => return Ok(Reply::Packet(format!("S{:02x}", error_signal(&err)))),
+        }
+        if connection.interrupted()? {
+            chip8.refresh();
+            return Ok(Reply::Packet(format!("S{:02x}", SIGINT)));
+        }
+    }
+    // silence the buzzer while stopped
+    chip8.refresh();
+
+    Ok(match chip8.break_reason() {
+        Some(BreakReason::Interrupted) => Reply::Packet(format!("S{:02x}", SIGINT)),
+        Some(_) => Reply::Packet(format!("S{:02x}", SIGTRAP)),
+        None if chip8.exited() => Reply::Close("W00".to_string()),
+        // the user closed the window
+        None => Reply::Close(format!("X{:02x}", SIGABRT)),
+    })
+}
+
+#[cfg(test)]
+fn gdb_session(rom: Vec<u8>, exchanges: &[(&str, &str)]) {
+    use std::thread;
+
+    let stub = GdbStub::bind(0).unwrap();
+    let addr = stub.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(rom).unwrap();
+        stub.serve(&mut chip8).unwrap();
+    });
+
+    let mut connection = Connection { stream: TcpStream::connect(addr).unwrap(), buffer: Vec::new() };
+    for (packet, expected) in exchanges {
+        connection.send(packet).unwrap();
+        assert_eq!(connection.read_packet(|| {}).unwrap().as_deref(), Some(*expected), "reply to {}", packet);
+    }
+    server.join().unwrap();
+}
+
+#[test]
+fn gdb_registers_and_memory() {
+    // LD V3, 0x42; LD I, 0x234
+    gdb_session(vec![0x63, 0x42, 0xA2, 0x34], &[
+        ("qSupported:multiprocess+", "PacketSize=1000;qXfer:features:read+"),
+        ("?", "S05"),
+        ("s", "S05"),
+        ("p3", "42"),
+        ("p11", "0202"),
+        ("P0=07", "OK"),
+        ("s", "S05"),
+        ("g", "0700004200000000000000000000000034020402000000"),
+        ("m200,4", "6342a234"),
+        ("M300,2:abcd", "OK"),
+        ("m300,3", "abcd00"),
+        ("m10000,1", "E01"),
+        ("mffffffffffffffff,1", "E01"),
+        ("m1,ffffffffffffffff", "E01"),
+        ("Mffffffffffffffff,1:ab", "E01"),
+        ("D", "OK"),
+    ]);
+}
+
+#[test]
+fn gdb_breakpoints_and_continue() {
+    // ADD V0, 1; SE V0, 3; JP 0x200; EXIT
+    gdb_session(vec![0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x00, 0xFD], &[
+        ("qXfer:features:read:target.xml:0,b", "m<?xml versi"),
+        ("qXfer:features:read:target.xml:ffffffffffffffff,1", "E01"),
+        (&format!("qXfer:features:read:target.xml:1,{:x}", usize::MAX), &format!("l{}", &target_xml()[1..])),
+        ("Z0,202,2", "OK"),
+        ("c", "S05"),
+        ("p0", "01"),
+        ("c", "S05"),
+        ("p0", "02"),
+        ("z0,202,2", "OK"),
+        ("c", "W00"),
+    ]);
+}
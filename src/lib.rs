@@ -0,0 +1,2 @@
+//! CHIP-8, SUPER-CHIP and XO-CHIP emulator core shared by the `chip8` binaries
+pub mod chip8;
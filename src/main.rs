@@ -3,107 +3,255 @@ extern crate sdl2;
 extern crate sdl2_sys;
 
 use std::env;
-use std::ffi::{CStr, CString};
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufWriter;
+use std::str::FromStr;
 
-use sdl2::event::Event;
-use sdl2::keyboard::{Keycode, Scancode};
-use sdl2::pixels::Color;
+use crate::beeper::{BeeperConfig, Waveform};
+use chip8::chip8::{compile_octo_file, Backend, Chip8, Movie, Quirks, QuirksPreset, RngKind, Timing, Tracer};
+use chip8::chip8::{DEFAULT_HASH_INTERVAL, FRAME_FREQUENCY, INSTR_PER_FRAME};
+use crate::debugger::Repl;
+use crate::gdb::GdbStub;
+use crate::sdl_backend::SdlBackend;
 
-use crate::chip8::Chip8;
+mod beeper;
+mod debugger;
+mod gdb;
+mod sdl_backend;
 
-pub mod chip8;
+const USAGE: &str = "Usage: chip8 [options] <rom>
+ROMs ending in .8o are compiled from Octo source
+Options:
+    --quirks vip|chip48|schip|xochip    interpreter quirks preset
+    --ipf <n>                           instructions executed per 60Hz frame (default 8)
+    --hz <n>                            instructions executed per second, instead of --ipf
+    --timing fixed|vip                  instruction timing, vip charges COSMAC VIP machine cycles (default fixed)
+    --seed <n>                          seed of the random number generator, random by default
+    --rng xorshift|table                random number generator, table adds bytes of the font area
+    --beep-freq <hz>                    buzzer frequency (default 440)
+    --volume <0-1>                      audio volume (default 0.2)
+    --waveform square|sine|triangle     buzzer waveform (default square)
+    --rewind <seconds>                  rewind history depth, 0 disables (default 30)
+    --debug                             start paused in the command-line debugger
+    --gdb <port>                        start paused, waiting for a GDB client on localhost
+    --trace <file>                      log every executed instruction with the registers to file
+    --trace-range <start>-<end>         only trace instructions in this hex address range, repeatable
+    --trace-last <n>                    only keep the last n instructions, written when an instruction fails
+    --record <file>                     record the keys of every frame to a movie file
+    --play <file>                       replay a movie file, reporting desyncs
+    --hash-interval <frames>            frames between state hashes in recorded movies, 0 disables (default 60)
+Keys:
+    M                                   mute/unmute
+    F1-F10                              load save slot 1-10
+    Shift+F1-F10                        save to slot 1-10
+    Backspace (hold)                    rewind
+    F12                                 break into the debugger (with --debug)
+    Escape                              quit";
 
-const DISPLAY_SCALE: usize = 8;
+/// Parse the value following option `name`
+fn parse_value<T: FromStr>(name: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("Missing value for {}", name))?;
+    value.parse::<T>().map_err(|_| format!("Invalid value {} for {}", value, name))
+}
 
-pub fn main() {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem.window("Chip8", chip8::DISPLAY_WIDTH as u32, chip8::DISPLAY_HEIGHT as u32)
-        .position_centered()
-        .resizable()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    unsafe {
-        sdl2_sys::SDL_SetHint(CString::new("SDL_RENDER_SCALE_QUALITY").unwrap().as_ptr(), CString::new("SDL_RENDER_SCALE_QUALITY").unwrap().as_ptr());
-        let err = sdl2_sys::SDL_RenderSetLogicalSize(sdl2_sys::SDL_GetRenderer(canvas.window_mut().raw()), chip8::DISPLAY_WIDTH as i32, chip8::DISPLAY_HEIGHT as i32);
-        //let err = sdl2_sys::SDL_RenderSetLogicalSize(sdl2_sys::SDL_GetRenderer(canvas.window_mut().raw()), chip8::DISPLAY_WIDTH as i32, chip8::DISPLAY_HEIGHT as i32);
-        if err != 0 {
-            panic!("ERR setting resolution {}", CStr::from_ptr(sdl2_sys::SDL_GetError()).to_str().unwrap())
-        }
-    }
+/// Parse a `start-end` range of hex addresses following option `name`
+fn parse_range(name: &str, value: Option<String>) -> Result<(u16, u16), String> {
+    let value = value.ok_or(format!("Missing value for {}", name))?;
+    let invalid = || format!("Invalid range {} for {}, expected <start>-<end> in hex", value, name);
+    let mut bounds = value.splitn(2, '-')
+        .map(|bound| u16::from_str_radix(bound.trim_start_matches("0x"), 16).map_err(|_| invalid()));
+    let start = bounds.next().ok_or_else(invalid)??;
+    let end = bounds.next().ok_or_else(invalid)??;
+    Ok((start, end))
+}
+
+struct Options {
+    quirks: Quirks,
+    cpu_frequency: u32,
+    timing: Timing,
+    seed: Option<u64>,
+    rng: RngKind,
+    beeper: BeeperConfig,
+    rewind_seconds: u32,
+    debug: bool,
+    gdb_port: Option<u16>,
+    trace_path: Option<String>,
+    trace_ranges: Vec<(u16, u16)>,
+    trace_last: Option<usize>,
+    record_path: Option<String>,
+    play_path: Option<String>,
+    hash_interval: u32,
+    rom_path: String,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut quirks = Quirks::default();
+    let mut cpu_frequency = INSTR_PER_FRAME * FRAME_FREQUENCY;
+    let mut timing = Timing::Fixed;
+    let mut seed = None;
+    let mut rng = RngKind::XorShift;
+    let mut beeper = BeeperConfig::default();
+    let mut rewind_seconds = 30;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut trace_path = None;
+    let mut trace_ranges = Vec::new();
+    let mut trace_last = None;
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut hash_interval = DEFAULT_HASH_INTERVAL;
+    let mut rom_path = None;
 
-    canvas.window_mut().set_size((chip8::DISPLAY_WIDTH * DISPLAY_SCALE) as u32, (chip8::DISPLAY_HEIGHT * DISPLAY_SCALE) as u32).unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-
-    let mut check_input = move |running: &mut bool, keypad: &mut Vec<bool>| {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    *running = false;
-                }
-                _ => {}
-            }
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = Quirks::from(args.next().unwrap_or_default().parse::<QuirksPreset>()?),
+            "--ipf" => cpu_frequency = parse_value::<u32>(&arg, args.next())?.checked_mul(FRAME_FREQUENCY)
+                .ok_or(format!("Value too large for {}", arg))?,
+            "--hz" => cpu_frequency = parse_value(&arg, args.next())?,
+            "--timing" => timing = args.next().unwrap_or_default().parse()?,
+            "--seed" => seed = Some(parse_value(&arg, args.next())?),
+            "--rng" => rng = args.next().unwrap_or_default().parse()?,
+            "--beep-freq" => beeper.frequency = parse_value(&arg, args.next())?,
+            "--volume" => beeper.volume = parse_value::<f32>(&arg, args.next())?.clamp(0.0, 1.0),
+            "--waveform" => beeper.waveform = args.next().unwrap_or_default().parse::<Waveform>()?,
+            "--rewind" => rewind_seconds = parse_value(&arg, args.next())?,
+            "--debug" => debug = true,
+            "--gdb" => gdb_port = Some(parse_value(&arg, args.next())?),
+            "--trace" => trace_path = Some(parse_value::<String>(&arg, args.next())?),
+            "--trace-range" => trace_ranges.push(parse_range(&arg, args.next())?),
+            "--trace-last" => trace_last = Some(parse_value(&arg, args.next())?),
+            "--record" => record_path = Some(parse_value::<String>(&arg, args.next())?),
+            "--play" => play_path = Some(parse_value::<String>(&arg, args.next())?),
+            "--hash-interval" => hash_interval = parse_value(&arg, args.next())?,
+            _ if rom_path.is_none() && !arg.starts_with("--") => rom_path = Some(arg),
+            _ => return Err(USAGE.to_string()),
         }
+    }
 
-        let keyboard = sdl2::keyboard::KeyboardState::new(&event_pump);
-        keypad[1] = keyboard.is_scancode_pressed(Scancode::Num1);
-        keypad[2] = keyboard.is_scancode_pressed(Scancode::Num2);
-        keypad[3] = keyboard.is_scancode_pressed(Scancode::Num3);
-        keypad[12] = keyboard.is_scancode_pressed(Scancode::Num4);
-
-        keypad[4] = keyboard.is_scancode_pressed(Scancode::Q);
-        keypad[5] = keyboard.is_scancode_pressed(Scancode::W);
-        keypad[6] = keyboard.is_scancode_pressed(Scancode::E);
-        keypad[13] = keyboard.is_scancode_pressed(Scancode::R);
-
-        keypad[7] = keyboard.is_scancode_pressed(Scancode::A);
-        keypad[8] = keyboard.is_scancode_pressed(Scancode::S);
-        keypad[9] = keyboard.is_scancode_pressed(Scancode::D);
-        keypad[14] = keyboard.is_scancode_pressed(Scancode::F);
-
-        keypad[10] = keyboard.is_scancode_pressed(Scancode::Z);
-        keypad[11] = keyboard.is_scancode_pressed(Scancode::X);
-        keypad[0] = keyboard.is_scancode_pressed(Scancode::C);
-        keypad[15] = keyboard.is_scancode_pressed(Scancode::V);
-    };
+    Ok(Options {
+        quirks,
+        cpu_frequency,
+        timing,
+        seed,
+        rng,
+        beeper,
+        rewind_seconds,
+        debug,
+        gdb_port,
+        trace_path,
+        trace_ranges,
+        trace_last,
+        record_path,
+        play_path,
+        hash_interval,
+        rom_path: rom_path.ok_or_else(|| USAGE.to_string())?,
+    })
+}
 
-    let mut render = move |display: &Vec<Vec<u8>>| {
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.clear();
-        for i in 0..chip8::DISPLAY_HEIGHT {
-            for j in 0..chip8::DISPLAY_WIDTH {
-                let color = if display[i][j] == 1 {
-                    Color::RGB(0, 0, 0)
-                } else {
-                    Color::RGB(255, 255, 255)
-                };
-                canvas.set_draw_color(color);
-                canvas.draw_point(sdl2::rect::Point::new(j as i32, i as i32)).unwrap();
-            }
+pub fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}", err);
+            return;
         }
-        canvas.present();
     };
 
-    let play_sound = || {};
+    let backend = SdlBackend::new(options.beeper).unwrap();
+    let mut chip8 = Chip8::new_with_quirks(backend, options.quirks);
+    chip8.set_rng(options.rng.create(options.seed));
+    chip8.set_cpu_frequency(options.cpu_frequency);
+    chip8.set_timing(options.timing);
 
-    let mut chip8 = Chip8::new_with_backend(&mut render, &play_sound, &mut check_input);
+    let rom_buffer = if options.rom_path.ends_with(".8o") {
+        compile_octo_file(&options.rom_path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        })
+    } else {
+        let mut rom = File::open(&options.rom_path).unwrap();
+        let mut rom_buffer = Vec::new();
+        rom.read_to_end(&mut rom_buffer).unwrap();
+        rom_buffer
+    };
+
+    chip8.load_rom(rom_buffer.clone()).unwrap();
+    if let Some(path) = &options.play_path {
+        if let Err(err) = Movie::load(path).and_then(|movie| chip8.play_movie(movie, &rom_buffer)) {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        }
+    } else if options.record_path.is_some() {
+        let seed = options.seed.unwrap_or_else(rand::random);
+        chip8.start_recording(&rom_buffer, seed, options.rng, options.hash_interval);
+    }
+    chip8.set_save_path(&options.rom_path);
+    chip8.set_rewind_depth(options.rewind_seconds);
+    if let Some(path) = &options.trace_path {
+        let file = File::create(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1);
+        });
+        let mut tracer = Tracer::new(Box::new(BufWriter::new(file)));
+        for (start, end) in &options.trace_ranges {
+            tracer.add_range(*start, *end);
+        }
+        if let Some(n) = options.trace_last {
+            tracer.set_ring_buffer(n);
+        }
+        chip8.set_tracer(Some(tracer));
+    }
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: chip8 <rom>");
+    if let Some(port) = options.gdb_port {
+        let served = GdbStub::bind(port).and_then(|stub| {
+            println!("Waiting for GDB on {}", stub.local_addr()?);
+            stub.serve(&mut chip8)
+        });
+        finish_trace(&mut chip8);
+        finish_movie(&mut chip8, &options);
+        if let Err(err) = served {
+            eprintln!("GDB server error: {}", err);
+            std::process::exit(1);
+        }
         return;
     }
 
-    let mut rom = File::open(&args[1]).unwrap();
-    let mut rom_buffer = Vec::new();
-    rom.read_to_end(&mut rom_buffer).unwrap();
+    let result = if options.debug {
+        Repl::new().run(&mut chip8)
+    } else {
+        chip8.run()
+    };
+    finish_trace(&mut chip8);
+    finish_movie(&mut chip8, &options);
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
 
-    chip8.load_rom(rom_buffer).unwrap();
-    chip8.run().unwrap();
+/// Save the recorded movie or report the outcome of the playback
+fn finish_movie<B: Backend>(chip8: &mut Chip8<B>, options: &Options) {
+    if let Some(desync) = chip8.movie_desync() {
+        eprintln!("{}", desync);
+    } else if options.play_path.is_some() && chip8.movie_finished() {
+        println!("Movie played back without desync");
+    }
+    if let (Some(movie), Some(path)) = (chip8.stop_movie(), &options.record_path) {
+        if let Err(err) = movie.save(path) {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+}
+
+/// Flush the trace file and report write errors
+fn finish_trace<B: Backend>(chip8: &mut Chip8<B>) {
+    if let Some(tracer) = chip8.tracer_mut() {
+        if let Err(err) = tracer.flush() {
+            eprintln!("Trace error: {}", err);
+        } else if let Some(err) = tracer.error() {
+            eprintln!("Trace error: {}", err);
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::EventPump;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::Color;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::beeper::{Beeper, BeeperConfig};
+use chip8::chip8::{AudioStream, Backend, Command, Palette, DISPLAY_HEIGHT, DISPLAY_WIDTH, SAVE_SLOTS};
+
+const DISPLAY_SCALE: usize = 8;
+
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+/// Samples kept queued ahead of playback: two 60Hz frames
+const AUDIO_QUEUE_TARGET: usize = 2 * AUDIO_SAMPLE_RATE as usize / 60;
+
+/// Keyboard layout, KEYMAP[k] is the scancode mapped to chip8 key k
+const KEYMAP: [Scancode; 16] = [
+    Scancode::C, Scancode::Num1, Scancode::Num2, Scancode::Num3,
+    Scancode::Q, Scancode::W, Scancode::E, Scancode::A,
+    Scancode::S, Scancode::D, Scancode::Z, Scancode::X,
+    Scancode::Num4, Scancode::R, Scancode::F, Scancode::V,
+];
+
+/// Save slot keys, F1 is slot 1
+const SLOT_KEYS: [Keycode; SAVE_SLOTS] = [
+    Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5,
+    Keycode::F6, Keycode::F7, Keycode::F8, Keycode::F9, Keycode::F10,
+];
+
+pub struct SdlBackend {
+    canvas: Canvas<Window>,
+    event_pump: EventPump,
+    audio_queue: AudioQueue<f32>,
+    audio_buffer: Vec<f32>,
+    beeper: Beeper,
+    /// toggled with the M key
+    muted: bool,
+    commands: VecDeque<Command>,
+    /// logical size of the canvas, follows the emulated display resolution
+    resolution: (usize, usize),
+    quit: bool,
+}
+
+impl SdlBackend {
+    pub fn new(beeper_config: BeeperConfig) -> Result<SdlBackend, String> {
+        let sdl_context = sdl2::init()?;
+        let video_subsystem = sdl_context.video()?;
+        let window = video_subsystem.window("Chip8", DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+
+        unsafe {
+            sdl2_sys::SDL_SetHint(CString::new("SDL_RENDER_SCALE_QUALITY").unwrap().as_ptr(), CString::new("SDL_RENDER_SCALE_QUALITY").unwrap().as_ptr());
+            let err = sdl2_sys::SDL_RenderSetLogicalSize(sdl2_sys::SDL_GetRenderer(canvas.window_mut().raw()), DISPLAY_WIDTH as i32, DISPLAY_HEIGHT as i32);
+            if err != 0 {
+                return Err(format!("ERR setting resolution {}", CStr::from_ptr(sdl2_sys::SDL_GetError()).to_str().unwrap()));
+            }
+        }
+
+        canvas.window_mut().set_size((DISPLAY_WIDTH * DISPLAY_SCALE) as u32, (DISPLAY_HEIGHT * DISPLAY_SCALE) as u32)
+            .map_err(|e| e.to_string())?;
+        let event_pump = sdl_context.event_pump()?;
+
+        let audio_spec = AudioSpecDesired {
+            freq: Some(AUDIO_SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let audio_queue = sdl_context.audio()?.open_queue::<f32, _>(None, &audio_spec)?;
+        audio_queue.resume();
+
+        Ok(SdlBackend {
+            canvas,
+            event_pump,
+            audio_queue,
+            audio_buffer: Vec::new(),
+            beeper: Beeper::new(beeper_config),
+            muted: false,
+            commands: VecDeque::new(),
+            resolution: (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+            quit: false,
+        })
+    }
+}
+
+impl Backend for SdlBackend {
+    fn render(&mut self, pixels: &[Vec<u8>], palette: &Palette) {
+        let resolution = (pixels[0].len(), pixels.len());
+        if resolution != self.resolution {
+            self.resolution = resolution;
+            self.canvas.set_logical_size(resolution.0 as u32, resolution.1 as u32).unwrap();
+        }
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        for (i, row) in pixels.iter().enumerate() {
+            for (j, pixel) in row.iter().enumerate() {
+                let (r, g, b) = palette[*pixel as usize & 3];
+                self.canvas.set_draw_color(Color::RGB(r, g, b));
+                self.canvas.draw_point(sdl2::rect::Point::new(j as i32, i as i32)).unwrap();
+            }
+        }
+        self.canvas.present();
+    }
+
+    fn set_sound(&mut self, on: bool) {
+        self.beeper.set_on(on);
+    }
+
+    /// Plays the XO-CHIP pattern once the program loaded one, the beeper otherwise
+    fn queue_audio(&mut self, audio: &mut AudioStream) {
+        let queued = self.audio_queue.size() as usize / std::mem::size_of::<f32>();
+        if queued >= AUDIO_QUEUE_TARGET {
+            return;
+        }
+
+        let sample_rate = self.audio_queue.spec().freq as u32;
+        self.audio_buffer.resize(AUDIO_QUEUE_TARGET - queued, 0.0);
+        if audio.pattern_loaded() {
+            audio.fill(&mut self.audio_buffer, sample_rate);
+        } else {
+            self.beeper.fill(&mut self.audio_buffer, sample_rate);
+        }
+
+        let volume = if self.muted { 0.0 } else { self.beeper.volume() };
+        for sample in &mut self.audio_buffer {
+            *sample *= volume;
+        }
+        self.audio_queue.queue(&self.audio_buffer);
+    }
+
+    fn poll_input(&mut self, keypad: &mut [bool]) {
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    self.quit = true;
+                }
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    self.muted = !self.muted;
+                }
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    self.commands.push_back(Command::Break);
+                }
+                // F1-F10 load a save slot, Shift+F1-F10 save it
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } => {
+                    if let Some(index) = SLOT_KEYS.iter().position(|key| *key == keycode) {
+                        let slot = index + 1;
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            self.commands.push_back(Command::SaveState(slot));
+                        } else {
+                            self.commands.push_back(Command::LoadState(slot));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let keyboard = sdl2::keyboard::KeyboardState::new(&self.event_pump);
+        for (key, scancode) in KEYMAP.iter().enumerate() {
+            keypad[key] = keyboard.is_scancode_pressed(*scancode);
+        }
+
+        if keyboard.is_scancode_pressed(Scancode::Backspace) {
+            self.commands.push_back(Command::Rewind);
+        }
+    }
+
+    fn quit_requested(&mut self) -> bool {
+        self.quit
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    fn command_done(&mut self, command: Command, result: Result<(), String>) {
+        let message = match (command, result) {
+            (_, Err(err)) => err,
+            (Command::SaveState(slot), Ok(())) => format!("Saved state to slot {}", slot),
+            (Command::LoadState(slot), Ok(())) => format!("Loaded state from slot {}", slot),
+            (Command::Rewind, Ok(())) => "Rewinding".to_string(),
+            (Command::Break, Ok(())) => "Paused".to_string(),
+        };
+        self.canvas.window_mut().set_title(&format!("Chip8 - {}", message)).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+//! Golden-framebuffer regression tests: each bundled ROM runs headlessly with a seeded RNG
+//! and scripted keys, then its display is compared to the ASCII art in `tests/golden/`.
+//! Run with `UPDATE_GOLDEN=1` to rewrite the golden files after an intended change.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use chip8::chip8::{framebuffer_ascii, Chip8, KeyScript};
+
+const SEED: u64 = 0xC8;
+
+/// (ROM in roms/, frames to run, key script)
+const ROMS: [(&str, u32, &str); 9] = [
+    ("Airplane.ch8", 300, "60=8 64= 180=8 184="),
+    ("Bowling.ch8", 300, "30=1 34= 90=1 94= 150=2 154="),
+    ("CAVE.ch8", 300, "30=F 34= 60=6 120=2 180=6 240="),
+    ("PONG.ch8", 300, "30=1 90=4 150=C 210=D 270="),
+    ("Puzzle.ch8", 300, "120=6 124= 180=2 184= 240=4 244="),
+    ("Space Invaders.ch8", 300, "30=5 34= 120=4 160=6 200=5 204="),
+    ("Tetris.ch8", 300, "60=6 64= 90=4 94= 120=5 124= 180=7"),
+    ("Tron.ch8", 300, "30=5 34= 90=8 150=6 210="),
+    ("test.ch8", 120, ""),
+];
+
+/// Expected and actual side by side, differing rows marked with `!` and pixels with `^`
+fn ascii_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let width = expected.iter().chain(&actual).map(|line| line.len()).max().unwrap_or(0).max("expected".len());
+
+    let mut diff = format!("  {:<width$} | {}\n", "expected", "actual", width = width);
+    for row in 0..expected.len().max(actual.len()) {
+        let left = expected.get(row).copied().unwrap_or("");
+        let right = actual.get(row).copied().unwrap_or("");
+        let marker = if left == right { ' ' } else { '!' };
+        diff.push_str(&format!("{} {:<width$} | {}\n", marker, left, right, width = width));
+        if left != right {
+            let pixels: String = (0..width)
+                .map(|col| if left.as_bytes().get(col) != right.as_bytes().get(col) { '^' } else { ' ' })
+                .collect();
+            diff.push_str(&format!("  {:<width$} | {}\n", pixels, pixels.trim_end(), width = width));
+        }
+    }
+    diff
+}
+
+fn run_rom(name: &str, frames: u32, keys: &str) -> String {
+    let rom = fs::read(Path::new("roms").join(name)).unwrap();
+    let mut chip8 = Chip8::new();
+    chip8.seed_rng(SEED);
+    chip8.load_rom(rom).unwrap();
+    let script: KeyScript = keys.parse().unwrap();
+    chip8.run_headless(frames, &script).unwrap_or_else(|err| panic!("{}: {}", name, err));
+    framebuffer_ascii(chip8.pixels())
+}
+
+#[test]
+fn bundled_roms_match_golden_framebuffers() {
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for (name, frames, keys) in ROMS.iter() {
+        let actual = run_rom(name, *frames, keys);
+        let golden = Path::new("tests/golden").join(format!("{}.txt", name));
+        if update {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&golden)
+            .unwrap_or_else(|err| panic!("{}: {}, run with UPDATE_GOLDEN=1 to create it", golden.display(), err));
+        if expected != actual {
+            println!("{} after {} frames differs from {}:\n{}", name, frames, golden.display(), ascii_diff(&expected, &actual));
+            failures.push(*name);
+        }
+    }
+    assert!(failures.is_empty(), "framebuffer mismatch for {:?}", failures);
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    let (name, frames, keys) = ROMS[5];
+    assert_eq!(run_rom(name, frames, keys), run_rom(name, frames, keys));
+}
+
+#[test]
+fn ascii_diff_marks_differences() {
+    assert_eq!(ascii_diff("#.\n..\n", "#.\n.#\n"), "  expected | actual\n  #.       | #.\n! ..       | .#\n   ^       |  ^\n");
+}